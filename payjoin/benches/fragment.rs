@@ -0,0 +1,23 @@
+use std::str::FromStr;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use payjoin::{HpkeKeyPair, PayjoinEndpoint};
+
+const ENDPOINT: &str = "HTTPS://EXAMPLE.COM/\
+                        #EX1XPK8Y6Q-OH1QYP87E2AVMDKXDTU6R25WCPQ5ZUF02XHNPA65JMD8ZA2W4YRQN6UUWG\
+                        -RK1QG2RH36X9ZWRK7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG";
+
+fn set_fragment_param(c: &mut Criterion) {
+    let endpoint = PayjoinEndpoint::from_str(ENDPOINT).unwrap();
+    let pubkey = HpkeKeyPair::gen_keypair().public_key().clone();
+    c.bench_function("set_fragment_param", |b| {
+        b.iter(|| {
+            let mut endpoint = endpoint.clone();
+            endpoint.rotate_receiver_pubkey(black_box(pubkey.clone()));
+            endpoint
+        })
+    });
+}
+
+criterion_group!(benches, set_fragment_param);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use payjoin::Uri;
+
+const URI: &str = "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?amount=0.01&label=coffee\
+                   &pjos=0&pj=HTTPS://EXAMPLE.COM/\
+                   %23EX1XPK8Y6Q-OH1QYP87E2AVMDKXDTU6R25WCPQ5ZUF02XHNPA65JMD8ZA2W4YRQN6UUWG\
+                   -RK1QG2RH36X9ZWRK7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG";
+
+fn parse_uri(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_uri");
+    group.bench_function("borrowed", |b| b.iter(|| Uri::try_from(black_box(URI)).unwrap()));
+    group.bench_function("owned", |b| b.iter(|| Uri::try_from(black_box(URI).to_owned()).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, parse_uri);
+criterion_main!(benches);
@@ -0,0 +1,70 @@
+#![no_main]
+
+use std::str::FromStr;
+
+use libfuzzer_sys::fuzz_target;
+use payjoin::bitcoin::bech32::Hrp;
+use payjoin::{validate_fragment, OhttpKeys, PayjoinEndpoint, Url, UrlExt};
+
+const OHTTP_KEYS: &str = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+
+// Feed arbitrary fragments through endpoint parsing and the fragment parameter getters
+fn parse_endpoint(fragment: &str) {
+    let endpoint = match PayjoinEndpoint::from_str(&format!("https://example.com/#{fragment}")) {
+        Ok(endpoint) => endpoint,
+        Err(_) => return,
+    };
+    let _ = endpoint.ohttp();
+    let _ = endpoint.receiver_pubkey();
+    let _ = endpoint.exp();
+    let _ = endpoint.exp_timestamp();
+
+    // Whatever parsed must parse again after serialization
+    PayjoinEndpoint::from_str(&endpoint.to_string()).expect("serialized endpoint must parse");
+}
+
+// Feed raw URLs, whose fragments skipped validation, through the fragment setters. The input is
+// the fragment, optionally followed by a newline and a param to set.
+fn set_params(data: &[u8]) {
+    let timestamp = match data.get(..4) {
+        Some(bytes) => u32::from_be_bytes(bytes.try_into().expect("four bytes")),
+        None => return,
+    };
+    let input = String::from_utf8_lossy(&data[4..]);
+    let (fragment, param) = input.split_once('\n').unwrap_or((&input, ""));
+    let url = match Url::parse(&format!("https://example.com/#{fragment}")) {
+        Ok(url) => url,
+        Err(_) => return,
+    };
+
+    let mut normalized = url.clone();
+    let _ = normalized.normalize_fragment();
+    let mut merged = Url::parse("https://example.com/#EX1C4UC6ES").expect("valid url");
+    let _ = merged.merge_fragment_from(&url);
+    let _ = url.clone().merge_fragment_from(&merged);
+
+    if let Some(hrp) = param.rsplit_once('1').and_then(|(hrp, _)| Hrp::parse(hrp).ok()) {
+        let mut url = url.clone();
+        if url.set_fragment_param(hrp, Some(param)).is_ok()
+            && validate_fragment(url.fragment().unwrap_or("")).is_ok()
+        {
+            assert_eq!(url.fragment_param(hrp), Ok(Some(param)), "set param must be read back");
+        }
+        let _ = url.set_fragment_param(hrp, None);
+    }
+
+    let mut url = url;
+    let exp = std::time::UNIX_EPOCH + std::time::Duration::from_secs(timestamp.into());
+    url.set_exp(exp).expect("u32 timestamps are always encodable");
+    url.set_ohttp(OhttpKeys::from_str(OHTTP_KEYS).expect("valid ohttp keys"));
+    if validate_fragment(url.fragment().unwrap_or("")).is_ok() {
+        assert_eq!(url.exp_timestamp().ok(), Some(timestamp), "set exp must be read back");
+        assert!(url.ohttp().is_ok(), "set ohttp keys must be read back");
+    }
+    url.clear_exp();
+}
+
+fuzz_target!(|data: &[u8]| {
+    parse_endpoint(&String::from_utf8_lossy(data));
+    set_params(data);
+});
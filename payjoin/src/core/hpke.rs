@@ -10,6 +10,9 @@ use hpke::kem::SecpK256HkdfSha256;
 use hpke::rand_core::OsRng;
 use hpke::{Deserializable, OpModeR, OpModeS, Serializable};
 use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use crate::uri::url_ext::RK_HRP;
 
 pub const PADDED_MESSAGE_BYTES: usize = 7168;
 pub const PADDED_PLAINTEXT_A_LENGTH: usize =
@@ -33,13 +36,13 @@ impl From<HpkeKeyPair> for (HpkeSecretKey, HpkePublicKey) {
 
 impl HpkeKeyPair {
     pub fn from_secret_key(secret_key: &HpkeSecretKey) -> Self {
-        let public_key = <SecpK256HkdfSha256 as hpke::Kem>::sk_to_pk(&secret_key.0);
+        let public_key = <SecpK256HkdfSha256 as hpke::Kem>::sk_to_pk(&secret_key.to_secret_key());
         Self(secret_key.clone(), HpkePublicKey(public_key))
     }
 
     pub fn gen_keypair() -> Self {
         let (sk, pk) = <SecpK256HkdfSha256 as hpke::Kem>::gen_keypair(&mut OsRng);
-        Self(HpkeSecretKey(sk), HpkePublicKey(pk))
+        Self(HpkeSecretKey::from_secret_key(&sk), HpkePublicKey(pk))
     }
     pub fn secret_key(&self) -> &HpkeSecretKey { &self.0 }
     pub fn public_key(&self) -> &HpkePublicKey { &self.1 }
@@ -79,15 +82,40 @@ fn ellswift_bytes_from_encapped_key(
     Ok(ellswift.to_array())
 }
 
-#[derive(Clone, PartialEq, Eq)]
-pub struct HpkeSecretKey(pub SecretKey);
+/// A secret key whose bytes are zeroed on drop
+///
+/// The HPKE secret key type does not expose its bytes to be wiped, so only the secret scalar is
+/// kept and a [`SecretKey`] is built from it for each operation. Those short lived copies, like
+/// any the compiler makes when moving the key, are not wiped.
+#[derive(Clone)]
+pub struct HpkeSecretKey(Zeroizing<[u8; 32]>);
+
+impl HpkeSecretKey {
+    fn from_secret_key(secret_key: &SecretKey) -> Self {
+        let mut encoded = secret_key.to_bytes();
+        let mut bytes = Zeroizing::new([0u8; 32]);
+        bytes.copy_from_slice(&encoded);
+        encoded.as_mut_slice().zeroize();
+        Self(bytes)
+    }
 
-impl Deref for HpkeSecretKey {
-    type Target = SecretKey;
+    /// Build the HPKE secret key, which is not zeroed on drop, for a single operation
+    pub(crate) fn to_secret_key(&self) -> SecretKey {
+        SecretKey::from_bytes(&self.0[..]).expect("bytes of a valid secret key")
+    }
+}
 
-    fn deref(&self) -> &Self::Target { &self.0 }
+impl ZeroizeOnDrop for HpkeSecretKey {}
+
+impl PartialEq for HpkeSecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        use subtle::ConstantTimeEq;
+        self.0[..].ct_eq(&other.0[..]).into()
+    }
 }
 
+impl Eq for HpkeSecretKey {}
+
 impl core::fmt::Debug for HpkeSecretKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "SecpHpkeSecretKey([REDACTED])")
@@ -99,7 +127,7 @@ impl serde::Serialize for HpkeSecretKey {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_bytes(&self.0.to_bytes())
+        serializer.serialize_bytes(&self.0[..])
     }
 }
 
@@ -108,11 +136,10 @@ impl<'de> serde::Deserialize<'de> for HpkeSecretKey {
     where
         D: serde::Deserializer<'de>,
     {
-        let bytes = Vec::<u8>::deserialize(deserializer)?;
-        Ok(HpkeSecretKey(
-            SecretKey::from_bytes(&bytes)
-                .map_err(|_| serde::de::Error::custom("Invalid secret key"))?,
-        ))
+        let bytes = Zeroizing::new(Vec::<u8>::deserialize(deserializer)?);
+        let secret_key = SecretKey::from_bytes(&bytes)
+            .map_err(|_| serde::de::Error::custom("Invalid secret key"))?;
+        Ok(HpkeSecretKey::from_secret_key(&secret_key))
     }
 }
 
@@ -120,13 +147,35 @@ impl<'de> serde::Deserialize<'de> for HpkeSecretKey {
 pub struct HpkePublicKey(pub PublicKey);
 
 impl HpkePublicKey {
+    /// The 33 byte compressed SEC1 encoding of the key, without the bech32 encoding of the URI
     pub fn to_compressed_bytes(&self) -> [u8; 33] {
         let compressed_key = secp256k1::PublicKey::from_slice(&self.0.to_bytes())
             .expect("Invalid public key from known valid bytes");
         compressed_key.serialize()
     }
 
+    /// The short ID of the directory mailbox belonging to this key
+    pub fn short_id(&self) -> crate::directory::ShortId { self.into() }
+
+    /// Compare two keys in constant time
+    ///
+    /// Prefer this over `==` when checking a key received from a counterparty against the
+    /// expected one, so the comparison does not leak timing information.
+    pub fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        use subtle::ConstantTimeEq;
+        self.to_compressed_bytes().ct_eq(&other.to_compressed_bytes())
+    }
+
+    /// Parse a key from its 33 byte compressed SEC1 encoding
+    ///
+    /// This is the inverse of [`HpkePublicKey::to_compressed_bytes`].
     pub fn from_compressed_bytes(bytes: &[u8]) -> Result<Self, HpkeError> {
+        if bytes.len() != PUBLIC_KEY_SIZE {
+            return Err(HpkeError::InvalidKeyLength {
+                expected: PUBLIC_KEY_SIZE,
+                got: bytes.len(),
+            });
+        }
         let compressed_key = secp256k1::PublicKey::from_slice(bytes)?;
         Ok(HpkePublicKey(PublicKey::from_bytes(
             compressed_key.serialize_uncompressed().as_slice(),
@@ -146,6 +195,30 @@ impl core::fmt::Debug for HpkePublicKey {
     }
 }
 
+/// The bech32 `RK1...` string used for the receiver key parameter of a payjoin endpoint
+impl fmt::Display for HpkePublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        crate::bech32::nochecksum::encode_to_fmt(f, RK_HRP, &self.to_compressed_bytes())
+            .map_err(|_| fmt::Error)
+    }
+}
+
+impl std::str::FromStr for HpkePublicKey {
+    type Err = ParseHpkePublicKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, bytes) =
+            crate::bech32::nochecksum::decode(s).map_err(ParseHpkePublicKeyError::Bech32)?;
+        if hrp != RK_HRP {
+            return Err(ParseHpkePublicKeyError::InvalidHrp {
+                expected: Box::new(RK_HRP),
+                actual: Box::new(hrp),
+            });
+        }
+        HpkePublicKey::from_compressed_bytes(&bytes).map_err(ParseHpkePublicKeyError::InvalidKey)
+    }
+}
+
 impl serde::Serialize for HpkePublicKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -207,7 +280,7 @@ pub fn decrypt_message_a(
         ChaCha20Poly1305,
         HkdfSha256,
         SecpK256HkdfSha256,
-    >(&OpModeR::Base, &receiver_sk.0, &enc, INFO_A)?;
+    >(&OpModeR::Base, &receiver_sk.to_secret_key(), &enc, INFO_A)?;
 
     let mut ciphertext = Vec::new();
     cursor.read_to_end(&mut ciphertext).map_err(|_| HpkeError::PayloadTooShort)?;
@@ -229,7 +302,7 @@ pub fn encrypt_message_b(
     let (encapsulated_key, mut encryption_context) =
         hpke::setup_sender::<ChaCha20Poly1305, HkdfSha256, SecpK256HkdfSha256, _>(
             &OpModeS::Auth((
-                receiver_keypair.secret_key().0.clone(),
+                receiver_keypair.secret_key().to_secret_key(),
                 receiver_keypair.public_key().0.clone(),
             )),
             &sender_pk.0,
@@ -250,11 +323,12 @@ pub fn decrypt_message_b(
 ) -> Result<Vec<u8>, HpkeError> {
     let enc = message_b.get(..ELLSWIFT_ENCODING_SIZE).ok_or(HpkeError::PayloadTooShort)?;
     let enc = encapped_key_from_ellswift_bytes(enc)?;
+    let sender_sk = sender_sk.to_secret_key();
     let mut decryption_ctx = hpke::setup_receiver::<
         ChaCha20Poly1305,
         HkdfSha256,
         SecpK256HkdfSha256,
-    >(&OpModeR::Auth(receiver_pk.0), &sender_sk.0, &enc, INFO_B)?;
+    >(&OpModeR::Auth(receiver_pk.0), &sender_sk, &enc, INFO_B)?;
     let plaintext = decryption_ctx
         .open(message_b.get(ELLSWIFT_ENCODING_SIZE..).ok_or(HpkeError::PayloadTooShort)?, &[])?;
     Ok(plaintext)
@@ -271,10 +345,17 @@ fn pad_plaintext(msg: &mut Vec<u8>, padded_length: usize) -> Result<&[u8], HpkeE
 /// Error from de/encrypting a v2 Hybrid Public Key Encryption payload.
 #[derive(Debug, PartialEq, Eq)]
 pub enum HpkeError {
-    InvalidPublicKey,
+    /// The key has the right length but is not a point on the curve
+    InvalidPoint,
     Hpke(hpke::HpkeError),
-    InvalidKeyLength,
-    PayloadTooLarge { actual: usize, max: usize },
+    InvalidKeyLength {
+        expected: usize,
+        got: usize,
+    },
+    PayloadTooLarge {
+        actual: usize,
+        max: usize,
+    },
     PayloadTooShort,
 }
 
@@ -287,7 +368,7 @@ impl From<secp256k1::Error> for HpkeError {
         match value {
             // As of writing, this is the only relevant variant that could arise here.
             // This may need to be updated if relevant variants are added to secp256k1
-            secp256k1::Error::InvalidPublicKey => Self::InvalidPublicKey,
+            secp256k1::Error::InvalidPublicKey => Self::InvalidPoint,
             _ => panic!("Unsupported variant of secp256k1::Error"),
         }
     }
@@ -299,7 +380,8 @@ impl fmt::Display for HpkeError {
 
         match &self {
             Hpke(e) => e.fmt(f),
-            InvalidKeyLength => write!(f, "Invalid Length"),
+            InvalidKeyLength { expected, got } =>
+                write!(f, "Invalid key length, expected {expected} bytes, got {got} bytes"),
             PayloadTooLarge { actual, max } => {
                 write!(
                     f,
@@ -307,7 +389,43 @@ impl fmt::Display for HpkeError {
                 )
             }
             PayloadTooShort => write!(f, "Payload too small"),
-            InvalidPublicKey => write!(f, "Invalid public key"),
+            InvalidPoint => write!(f, "Invalid public key, not a point on the curve"),
+        }
+    }
+}
+
+/// Error parsing an [`HpkePublicKey`] from its bech32 string
+#[derive(Debug)]
+pub enum ParseHpkePublicKeyError {
+    /// The string is not valid bech32
+    Bech32(bitcoin::bech32::primitives::decode::CheckedHrpstringError),
+    /// The human readable part is not RK
+    InvalidHrp { expected: Box<bitcoin::bech32::Hrp>, actual: Box<bitcoin::bech32::Hrp> },
+    /// The data is not a compressed public key
+    InvalidKey(HpkeError),
+}
+
+impl fmt::Display for ParseHpkePublicKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ParseHpkePublicKeyError::*;
+
+        match &self {
+            Bech32(e) => write!(f, "public key is not valid bech32: {e}"),
+            InvalidHrp { expected, actual } =>
+                write!(f, "incorrect hrp for public key: expected {expected}, got {actual}"),
+            InvalidKey(e) => write!(f, "invalid public key: {e}"),
+        }
+    }
+}
+
+impl error::Error for ParseHpkePublicKeyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use ParseHpkePublicKeyError::*;
+
+        match &self {
+            Bech32(e) => Some(e),
+            InvalidHrp { .. } => None,
+            InvalidKey(e) => Some(e),
         }
     }
 }
@@ -319,8 +437,8 @@ impl error::Error for HpkeError {
         match &self {
             Hpke(e) => Some(e),
             PayloadTooLarge { .. } => None,
-            InvalidKeyLength | PayloadTooShort => None,
-            InvalidPublicKey => None,
+            InvalidKeyLength { .. } | PayloadTooShort => None,
+            InvalidPoint => None,
         }
     }
 }
@@ -329,6 +447,81 @@ impl error::Error for HpkeError {
 mod test {
     use super::*;
 
+    #[test]
+    fn secret_key_zeroize() {
+        fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+        assert_zeroize_on_drop::<HpkeSecretKey>();
+
+        // The bytes which are zeroed on drop are the key itself
+        let keypair = HpkeKeyPair::gen_keypair();
+        let secret_key = keypair.secret_key();
+        assert_eq!(&secret_key.0[..], keypair.secret_key().to_secret_key().to_bytes().as_slice());
+        assert_eq!(HpkeKeyPair::from_secret_key(secret_key), keypair);
+    }
+
+    #[test]
+    fn pubkey_compressed_bytes_roundtrip() {
+        let bytes = HpkeKeyPair::gen_keypair().public_key().to_compressed_bytes();
+        let pubkey = HpkePublicKey::from_compressed_bytes(&bytes).unwrap();
+        assert_eq!(pubkey.to_compressed_bytes(), bytes);
+    }
+
+    #[test]
+    fn pubkey_string_roundtrip() {
+        use std::str::FromStr;
+
+        let pubkey = HpkeKeyPair::gen_keypair().public_key().clone();
+        let encoded = pubkey.to_string();
+        assert!(encoded.starts_with("RK1"));
+        assert_eq!(HpkePublicKey::from_str(&encoded).unwrap(), pubkey);
+
+        let mut url = url::Url::parse("https://example.com").unwrap();
+        crate::uri::UrlExt::set_receiver_pubkey(&mut url, pubkey);
+        assert_eq!(url.fragment(), Some(encoded.as_str()));
+
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        assert!(matches!(
+            HpkePublicKey::from_str(ohttp),
+            Err(ParseHpkePublicKeyError::InvalidHrp { .. })
+        ));
+        assert!(matches!(
+            HpkePublicKey::from_str("RK1QQQ"),
+            Err(ParseHpkePublicKeyError::InvalidKey(HpkeError::InvalidKeyLength { .. }))
+        ));
+    }
+
+    #[test]
+    fn pubkey_ct_eq() {
+        let pubkey = HpkeKeyPair::gen_keypair().public_key().clone();
+        let other_pubkey = HpkeKeyPair::gen_keypair().public_key().clone();
+
+        assert!(bool::from(pubkey.ct_eq(&pubkey.clone())));
+        assert!(!bool::from(pubkey.ct_eq(&other_pubkey)));
+    }
+
+    #[test]
+    fn pubkey_from_compressed_bytes_errors() {
+        let pubkey = HpkeKeyPair::gen_keypair().public_key().to_compressed_bytes();
+
+        assert_eq!(
+            HpkePublicKey::from_compressed_bytes(&pubkey[..PUBLIC_KEY_SIZE - 1]),
+            Err(HpkeError::InvalidKeyLength {
+                expected: PUBLIC_KEY_SIZE,
+                got: PUBLIC_KEY_SIZE - 1
+            })
+        );
+
+        // 0x05 is not a valid compressed point prefix
+        let mut invalid_point = pubkey;
+        invalid_point[0] = 0x05;
+        assert_eq!(
+            HpkePublicKey::from_compressed_bytes(&invalid_point),
+            Err(HpkeError::InvalidPoint)
+        );
+
+        assert!(HpkePublicKey::from_compressed_bytes(&pubkey).is_ok());
+    }
+
     #[test]
     fn message_a_round_trip() {
         let mut plaintext = "foo".as_bytes().to_vec();
@@ -17,24 +17,41 @@ pub use request::*;
 pub(crate) mod into_url;
 pub use into_url::{Error as IntoUrlError, IntoUrl};
 pub(crate) mod uri;
-pub use uri::{PjParseError, PjUri, Uri, UriExt};
+pub use uri::error::BadEndpointError;
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+pub use uri::pj_uri_serde;
+pub use uri::policy::{DefaultEndpointPolicy, SecureEndpointPolicy, UnsecureEndpoint};
+#[cfg(feature = "v2")]
+pub use uri::url_ext::{
+    parse_with_fragment, parse_with_fragment_strict, validate_fragment, ParseExpParamError,
+    ParseFragmentError, ParseOhttpKeysParamError, ParseReceiverPubkeyParamError,
+    ParseV2ParamsError, SetExpError, SetFragmentParamError, UrlExt, V2Params,
+};
+pub use uri::{
+    extract_pj_endpoint, parse, try_from_strict_amount, ExplicitPjos, NotPayjoin, NotPayjoinReason,
+    PayjoinEndpoint, PjParseError, PjParseErrorKind, PjUri, PjUriBuilder, PjUriExt, PreservedPjos,
+    Uri, UriExt, UriPolicyExt, KNOWN_PARAMS,
+};
+#[cfg(feature = "v2")]
+pub use uri::{try_from_at, SecuritySummary};
 pub use url::{ParseError, Url};
 pub(crate) mod error_codes;
 
 pub(crate) mod output_substitution;
 #[cfg(feature = "v1")]
-pub use output_substitution::OutputSubstitution;
+pub use output_substitution::{OutputSubstitution, ParseOutputSubstitutionError};
 
 #[cfg(feature = "v2")]
 pub(crate) mod hpke;
 #[cfg(feature = "v2")]
 pub mod persist;
 #[cfg(feature = "v2")]
-pub use crate::hpke::{HpkeKeyPair, HpkePublicKey};
+pub use crate::hpke::{HpkeError, HpkeKeyPair, HpkePublicKey, ParseHpkePublicKeyError};
 #[cfg(feature = "v2")]
 pub(crate) mod ohttp;
 #[cfg(feature = "v2")]
-pub use crate::ohttp::OhttpKeys;
+pub use crate::ohttp::{OhttpKeys, ParseOhttpKeysError};
 
 #[cfg(feature = "io")]
 #[cfg_attr(docsrs, doc(cfg(feature = "io")))]
@@ -6,6 +6,7 @@ use bitcoin::key::constants::UNCOMPRESSED_PUBLIC_KEY_SIZE;
 use hpke::rand_core::{OsRng, RngCore};
 
 use crate::directory::ENCAPSULATED_MESSAGE_BYTES;
+use crate::uri::url_ext::OH_HRP;
 
 const N_ENC: usize = UNCOMPRESSED_PUBLIC_KEY_SIZE;
 const N_T: usize = crate::hpke::POLY1305_TAG_SIZE;
@@ -200,6 +201,15 @@ impl OhttpKeys {
     pub fn decode(bytes: &[u8]) -> Result<Self, ohttp::Error> {
         ohttp::KeyConfig::decode(bytes).map(Self)
     }
+
+    /// Parse a KeyConfig in the RFC 9458 wire format, e.g. as published by a directory
+    ///
+    /// This is [`OhttpKeys::decode`] with the errors of the bech32 [`std::str::FromStr`] impl.
+    /// Unlike `TryFrom<&[u8]>`, which takes the compact key id and compressed public key found in
+    /// a payjoin URI, the bytes include the KEM and symmetric suite ids.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseOhttpKeysError> {
+        Ok(Self(ohttp::KeyConfig::decode(bytes)?))
+    }
 }
 
 const KEM_ID: &[u8] = b"\x00\x16"; // DHKEM(secp256k1, HKDF-SHA256)
@@ -218,9 +228,7 @@ impl fmt::Display for OhttpKeys {
         let mut buf = vec![key_id];
         buf.extend_from_slice(&compressed_pubkey);
 
-        let oh_hrp: bech32::Hrp = bech32::Hrp::parse("OH").unwrap();
-
-        crate::bech32::nochecksum::encode_to_fmt(f, oh_hrp, &buf).map_err(|e| match e {
+        crate::bech32::nochecksum::encode_to_fmt(f, OH_HRP, &buf).map_err(|e| match e {
             EncodeError::Fmt(e) => e,
             _ => fmt::Error,
         })
@@ -231,8 +239,8 @@ impl TryFrom<&[u8]> for OhttpKeys {
     type Error = ParseOhttpKeysError;
 
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        let key_id = *bytes.first().ok_or(ParseOhttpKeysError::InvalidFormat)?;
-        let compressed_pk = bytes.get(1..34).ok_or(ParseOhttpKeysError::InvalidFormat)?;
+        let key_id = *bytes.first().ok_or(ParseOhttpKeysError::Truncated)?;
+        let compressed_pk = bytes.get(1..34).ok_or(ParseOhttpKeysError::Truncated)?;
 
         let pubkey = bitcoin::secp256k1::PublicKey::from_slice(compressed_pk)
             .map_err(|_| ParseOhttpKeysError::InvalidPublicKey)?;
@@ -243,7 +251,7 @@ impl TryFrom<&[u8]> for OhttpKeys {
         buf.extend_from_slice(SYMMETRIC_LEN);
         buf.extend_from_slice(SYMMETRIC_KDF_AEAD);
 
-        ohttp::KeyConfig::decode(&buf).map(Self).map_err(ParseOhttpKeysError::DecodeKeyConfig)
+        Ok(Self(ohttp::KeyConfig::decode(&buf)?))
     }
 }
 
@@ -254,13 +262,15 @@ impl std::str::FromStr for OhttpKeys {
     /// The string format is: key_id || compressed_public_key
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         // TODO extract to utility function
-        let oh_hrp: bech32::Hrp = bech32::Hrp::parse("OH").unwrap();
 
         let (hrp, bytes) =
-            crate::bech32::nochecksum::decode(s).map_err(ParseOhttpKeysError::DecodeBech32)?;
+            crate::bech32::nochecksum::decode(s).map_err(ParseOhttpKeysError::Bech32)?;
 
-        if hrp != oh_hrp {
-            return Err(ParseOhttpKeysError::InvalidFormat);
+        if hrp != OH_HRP {
+            return Err(ParseOhttpKeysError::InvalidHrp {
+                expected: Box::new(OH_HRP),
+                actual: Box::new(hrp),
+            });
         }
 
         Self::try_from(&bytes[..])
@@ -279,6 +289,11 @@ impl PartialEq for OhttpKeys {
 
 impl Eq for OhttpKeys {}
 
+impl std::hash::Hash for OhttpKeys {
+    /// Hash the encoded key config, consistent with [`PartialEq`]
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) { self.encode().ok().hash(state) }
+}
+
 impl Deref for OhttpKeys {
     type Target = ohttp::KeyConfig;
 
@@ -311,18 +326,42 @@ impl serde::Serialize for OhttpKeys {
 
 #[derive(Debug)]
 pub enum ParseOhttpKeysError {
-    InvalidFormat,
+    /// The human readable part is not OH
+    InvalidHrp {
+        expected: Box<bech32::Hrp>,
+        actual: Box<bech32::Hrp>,
+    },
     InvalidPublicKey,
-    DecodeBech32(bech32::primitives::decode::CheckedHrpstringError),
+    /// The key config is not valid bech32
+    Bech32(bech32::primitives::decode::CheckedHrpstringError),
+    /// The key config is missing bytes
+    Truncated,
+    /// The KEM, KDF or AEAD of the key config is not supported
+    UnsupportedSuite,
     DecodeKeyConfig(ohttp::Error),
 }
 
+impl From<ohttp::Error> for ParseOhttpKeysError {
+    fn from(value: ohttp::Error) -> Self {
+        match value {
+            ohttp::Error::Unsupported | ohttp::Error::InvalidKem => Self::UnsupportedSuite,
+            ohttp::Error::Truncated => Self::Truncated,
+            ohttp::Error::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Self::Truncated,
+            e => Self::DecodeKeyConfig(e),
+        }
+    }
+}
+
 impl std::fmt::Display for ParseOhttpKeysError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ParseOhttpKeysError::InvalidFormat => write!(f, "Invalid format"),
+            ParseOhttpKeysError::InvalidHrp { expected, actual } =>
+                write!(f, "Invalid HRP: expected {expected}, got {actual}"),
             ParseOhttpKeysError::InvalidPublicKey => write!(f, "Invalid public key"),
-            ParseOhttpKeysError::DecodeBech32(e) => write!(f, "Failed to decode bech32: {e}"),
+            ParseOhttpKeysError::Bech32(e) => write!(f, "Failed to decode bech32: {e}"),
+            ParseOhttpKeysError::Truncated => write!(f, "KeyConfig is truncated"),
+            ParseOhttpKeysError::UnsupportedSuite =>
+                write!(f, "KeyConfig uses an unsupported KEM, KDF or AEAD"),
             ParseOhttpKeysError::DecodeKeyConfig(e) => write!(f, "Failed to decode KeyConfig: {e}"),
         }
     }
@@ -331,9 +370,12 @@ impl std::fmt::Display for ParseOhttpKeysError {
 impl std::error::Error for ParseOhttpKeysError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            ParseOhttpKeysError::DecodeBech32(e) => Some(e),
+            ParseOhttpKeysError::Bech32(e) => Some(e),
             ParseOhttpKeysError::DecodeKeyConfig(e) => Some(e),
-            ParseOhttpKeysError::InvalidFormat | ParseOhttpKeysError::InvalidPublicKey => None,
+            ParseOhttpKeysError::InvalidHrp { .. }
+            | ParseOhttpKeysError::InvalidPublicKey
+            | ParseOhttpKeysError::Truncated
+            | ParseOhttpKeysError::UnsupportedSuite => None,
         }
     }
 }
@@ -344,6 +386,84 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn test_ohttp_keys_parse_errors() {
+        use std::str::FromStr;
+
+        assert!(matches!(
+            OhttpKeys::from_str("OH1invalid_bech_32"),
+            Err(ParseOhttpKeysError::Bech32(_))
+        ));
+        assert!(matches!(
+            OhttpKeys::from_str("OH1QYPM5JXYNS754"),
+            Err(ParseOhttpKeysError::Truncated)
+        ));
+        assert!(matches!(
+            OhttpKeys::from_str("RK1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC"),
+            Err(ParseOhttpKeysError::InvalidHrp { expected, actual })
+                if *expected == OH_HRP && actual.as_str() == "RK"
+        ));
+
+        // Replace the AEAD id at the end of the encoded key config with an unassigned one
+        let keys = OhttpKeys(ohttp::KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap());
+        let mut encoded = keys.encode().unwrap();
+        let len = encoded.len();
+        encoded[len - 2..].copy_from_slice(&[0xff, 0xfe]);
+        let error = ParseOhttpKeysError::from(ohttp::KeyConfig::decode(&encoded).unwrap_err());
+        assert!(matches!(error, ParseOhttpKeysError::UnsupportedSuite), "{error:?}");
+    }
+
+    #[test]
+    fn test_ohttp_keys_from_bytes() {
+        use std::str::FromStr;
+
+        // key id 1, DHKEM(secp256k1, HKDF-SHA256) with the generator point as public key,
+        // and HKDF-SHA256 with ChaCha20Poly1305
+        let mut key_config = vec![0x01, 0x00, 0x16];
+        key_config.extend_from_slice(&[
+            0x04, 0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce,
+            0x87, 0x0b, 0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81,
+            0x5b, 0x16, 0xf8, 0x17, 0x98, 0x48, 0x3a, 0xda, 0x77, 0x26, 0xa3, 0xc4, 0x65, 0x5d,
+            0xa4, 0xfb, 0xfc, 0x0e, 0x11, 0x08, 0xa8, 0xfd, 0x17, 0xb4, 0x48, 0xa6, 0x85, 0x54,
+            0x19, 0x9c, 0x47, 0xd0, 0x8f, 0xfb, 0x10, 0xd4, 0xb8,
+        ]);
+        key_config.extend_from_slice(&[0x00, 0x04, 0x00, 0x01, 0x00, 0x03]);
+
+        let keys = OhttpKeys::from_bytes(&key_config).unwrap();
+        let encoded = keys.encode().unwrap();
+        assert_eq!(encoded, key_config);
+        assert_eq!(encoded[0], 1);
+        assert_eq!(&encoded[1..3], KEM_ID);
+        assert_eq!(&encoded[68..70], SYMMETRIC_LEN);
+        assert_eq!(&encoded[70..], SYMMETRIC_KDF_AEAD);
+        assert_eq!(OhttpKeys::from_str(&keys.to_string()).unwrap(), keys);
+
+        let truncated = OhttpKeys::from_bytes(&key_config[..40]).unwrap_err();
+        assert!(matches!(truncated, ParseOhttpKeysError::Truncated), "{truncated:?}");
+        key_config[2] = 0x20; // DHKEM(X25519, HKDF-SHA256)
+        assert!(matches!(
+            OhttpKeys::from_bytes(&key_config),
+            Err(ParseOhttpKeysError::UnsupportedSuite)
+        ));
+    }
+
+    #[test]
+    fn test_ohttp_keys_hash() {
+        use std::collections::HashSet;
+
+        let keys = OhttpKeys(ohttp::KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap());
+        let other_keys =
+            OhttpKeys(ohttp::KeyConfig::new(KEY_ID + 1, KEM, Vec::from(SYMMETRIC)).unwrap());
+
+        let mut set = HashSet::new();
+        assert!(set.insert(keys.clone()));
+        assert!(!set.insert(keys.clone()));
+        assert!(set.insert(other_keys.clone()));
+        assert!(!set.insert(other_keys));
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&keys));
+    }
+
     #[test]
     fn test_ohttp_keys_roundtrip() {
         use std::str::FromStr;
@@ -4,3 +4,74 @@ pub enum OutputSubstitution {
     Enabled,
     Disabled,
 }
+
+impl OutputSubstitution {
+    /// Returns true if the receiver may substitute original outputs.
+    pub fn is_enabled(&self) -> bool { matches!(self, OutputSubstitution::Enabled) }
+
+    /// Returns true if the receiver must not substitute original outputs.
+    pub fn is_disabled(&self) -> bool { matches!(self, OutputSubstitution::Disabled) }
+}
+
+/// Parses the BIP78 `pjos` value, `"1"` for enabled and `"0"` for disabled.
+impl std::str::FromStr for OutputSubstitution {
+    type Err = ParseOutputSubstitutionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(OutputSubstitution::Disabled),
+            "1" => Ok(OutputSubstitution::Enabled),
+            _ => Err(ParseOutputSubstitutionError),
+        }
+    }
+}
+
+/// Formats the BIP78 `pjos` value, `"1"` for enabled and `"0"` for disabled.
+impl std::fmt::Display for OutputSubstitution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputSubstitution::Enabled => f.write_str("1"),
+            OutputSubstitution::Disabled => f.write_str("0"),
+        }
+    }
+}
+
+/// The string was neither `"0"` nor `"1"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOutputSubstitutionError;
+
+impl std::fmt::Display for ParseOutputSubstitutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "output substitution must be \"0\" or \"1\"")
+    }
+}
+
+impl std::error::Error for ParseOutputSubstitutionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predicates() {
+        assert!(OutputSubstitution::Enabled.is_enabled());
+        assert!(!OutputSubstitution::Enabled.is_disabled());
+        assert!(OutputSubstitution::Disabled.is_disabled());
+        assert!(!OutputSubstitution::Disabled.is_enabled());
+    }
+
+    #[test]
+    fn test_from_str_display() {
+        for value in [OutputSubstitution::Enabled, OutputSubstitution::Disabled] {
+            assert_eq!(value.to_string().parse::<OutputSubstitution>(), Ok(value));
+        }
+        assert_eq!("1".parse::<OutputSubstitution>(), Ok(OutputSubstitution::Enabled));
+        assert_eq!("0".parse::<OutputSubstitution>(), Ok(OutputSubstitution::Disabled));
+        assert_eq!(OutputSubstitution::Enabled.to_string(), "1");
+        assert_eq!(OutputSubstitution::Disabled.to_string(), "0");
+
+        for invalid in ["2", "", "00", "true"] {
+            assert_eq!(invalid.parse::<OutputSubstitution>(), Err(ParseOutputSubstitutionError));
+        }
+    }
+}
@@ -17,7 +17,7 @@ pub fn build_v1_pj_uri<'a>(
     endpoint: impl IntoUrl,
     output_substitution: OutputSubstitution,
 ) -> Result<crate::uri::PjUri<'a>, crate::into_url::Error> {
-    let extras = crate::uri::PayjoinExtras { endpoint: endpoint.into_url()?, output_substitution };
+    let extras = crate::uri::PayjoinExtras::new(endpoint.into_url()?, output_substitution);
     Ok(bitcoin_uri::Uri::with_extras(address.clone(), extras))
 }
 
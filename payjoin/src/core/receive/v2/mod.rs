@@ -27,7 +27,6 @@
 use std::str::FromStr;
 use std::time::{Duration, SystemTime};
 
-use bitcoin::hashes::{sha256, Hash};
 use bitcoin::psbt::Psbt;
 use bitcoin::{Address, FeeRate, OutPoint, Script, TxOut};
 pub(crate) use error::InternalSessionError;
@@ -52,6 +51,7 @@ use crate::persist::{
     MaybeSuccessTransition, MaybeTransientTransition, NextStateTransition,
 };
 use crate::receive::{parse_payload, InputPair};
+use crate::uri::url_ext::SetExpError;
 use crate::uri::ShortId;
 use crate::{ImplementationError, IntoUrl, IntoUrlError, Request, Version};
 
@@ -69,6 +69,7 @@ pub struct SessionContext {
     directory: url::Url,
     mailbox: Option<url::Url>,
     ohttp_keys: OhttpKeys,
+    #[serde(deserialize_with = "deserialize_expiry_capped")]
     expiry: SystemTime,
     s: HpkeKeyPair,
     e: Option<HpkePublicKey>,
@@ -89,9 +90,7 @@ impl SessionContext {
     }
 
     /// The per-session identifier
-    pub(crate) fn id(&self) -> ShortId {
-        sha256::Hash::hash(&self.s.public_key().to_compressed_bytes()).into()
-    }
+    pub(crate) fn id(&self) -> ShortId { self.s.public_key().short_id() }
 }
 
 fn deserialize_address_assume_checked<'de, D>(deserializer: D) -> Result<Address, D::Error>
@@ -103,8 +102,16 @@ where
     Ok(address.assume_checked())
 }
 
-fn short_id_from_pubkey(pubkey: &HpkePublicKey) -> ShortId {
-    sha256::Hash::hash(&pubkey.to_compressed_bytes()).into()
+/// Cap the expiry of a stored session like [`Receiver::create_session`] does
+///
+/// Sessions stored before the cap was introduced may expire later than the payjoin URI can
+/// encode.
+fn deserialize_expiry_capped<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let expiry = SystemTime::deserialize(deserializer)?;
+    Ok(expiry.min(crate::uri::url_ext::max_exp()))
 }
 
 /// Represents the various states of a Payjoin receiver session during the protocol flow.
@@ -281,7 +288,9 @@ impl Receiver<UninitializedReceiver> {
             directory,
             mailbox: None,
             ohttp_keys,
-            expiry: SystemTime::now() + expire_after.unwrap_or(TWENTY_FOUR_HOURS_DEFAULT_EXPIRY),
+            // Cap the expiry to what can be encoded in the payjoin URI
+            expiry: (SystemTime::now() + expire_after.unwrap_or(TWENTY_FOUR_HOURS_DEFAULT_EXPIRY))
+                .min(crate::uri::url_ext::max_exp()),
             s: HpkeKeyPair::gen_keypair(),
             e: None,
         };
@@ -949,22 +958,22 @@ impl Receiver<PayjoinProposal> {
         if let Some(e) = &self.context.e {
             // Prepare v2 payload
             let payjoin_bytes = self.v1.psbt().serialize();
-            let sender_mailbox = short_id_from_pubkey(e);
+            let sender_mailbox = e.short_id();
             target_resource = self
                 .context
                 .directory
-                .join(&sender_mailbox.to_string())
+                .join(&sender_mailbox.subdirectory_path())
                 .map_err(|e| ReplyableError::Implementation(ImplementationError::new(e)))?;
             body = encrypt_message_b(payjoin_bytes, &self.context.s, e)?;
             method = "POST";
         } else {
             // Prepare v2 wrapped and backwards-compatible v1 payload
             body = self.v1.psbt().to_string().as_bytes().to_vec();
-            let receiver_mailbox = short_id_from_pubkey(self.context.s.public_key());
+            let receiver_mailbox = self.context.s.public_key().short_id();
             target_resource = self
                 .context
                 .directory
-                .join(&receiver_mailbox.to_string())
+                .join(&receiver_mailbox.subdirectory_path())
                 .map_err(|e| ReplyableError::Implementation(ImplementationError::new(e)))?;
             method = "PUT";
         }
@@ -1013,18 +1022,41 @@ fn mailbox_endpoint(directory: &Url, id: &ShortId) -> Url {
     url
 }
 
+/// Build the payjoin endpoint of a receiver's mailbox on a directory
+///
+/// This joins the mailbox path to `directory` and sets the ohttp keys, receiver public key and
+/// optional expiry as fragment parameters. It fails if `exp` cannot be encoded.
+///
+/// Use [`UrlExt::with_v2_params`](crate::UrlExt::with_v2_params) to build such an endpoint on any
+/// other base URL.
+pub fn receiver_endpoint(
+    directory: &Url,
+    short_id: &ShortId,
+    ohttp: OhttpKeys,
+    rk: HpkePublicKey,
+    exp: Option<SystemTime>,
+) -> Result<Url, SetExpError> {
+    use crate::uri::UrlExt;
+    Url::with_v2_params(mailbox_endpoint(directory, short_id), ohttp, rk, exp)
+}
+
 /// Gets the Payjoin URI from a session context
 pub(crate) fn pj_uri<'a>(
     session_context: &SessionContext,
     output_substitution: OutputSubstitution,
 ) -> crate::PjUri<'a> {
     use crate::uri::{PayjoinExtras, UrlExt};
-    let id = session_context.id();
-    let mut pj = mailbox_endpoint(&session_context.directory, &id).clone();
-    pj.set_receiver_pubkey(session_context.s.public_key().clone());
+    let mut pj = mailbox_endpoint(&session_context.directory, &session_context.id());
     pj.set_ohttp(session_context.ohttp_keys.clone());
-    pj.set_exp(session_context.expiry);
-    let extras = PayjoinExtras { endpoint: pj, output_substitution };
+    pj.set_receiver_pubkey(session_context.s.public_key().clone());
+    // The expiry is capped when the session is created or deserialized, saturate rather than
+    // panic should that ever be bypassed
+    let exp = match session_context.expiry.duration_since(std::time::UNIX_EPOCH) {
+        Ok(exp) => u32::try_from(exp.as_secs()).unwrap_or(u32::MAX),
+        Err(_) => 0,
+    };
+    pj.set_exp_timestamp(exp);
+    let extras = PayjoinExtras::new(pj, output_substitution);
     bitcoin_uri::Uri::with_extras(session_context.address.clone(), extras)
 }
 
@@ -1078,6 +1110,31 @@ pub mod test {
         }
     }
 
+    #[test]
+    fn test_receiver_endpoint() {
+        let ohttp =
+            OhttpKeys::from_str("OH1QYP87E2AVMDKXDTU6R25WCPQ5ZUF02XHNPA65JMD8ZA2W4YRQN6UUWG")
+                .unwrap();
+        let rk =
+            HpkePublicKey::from_str("RK1QG2RH36X9ZWRK7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG")
+                .unwrap();
+        let directory = Url::parse("https://directory.example.com").unwrap();
+        let exp = SystemTime::UNIX_EPOCH + Duration::from_secs(1720547781);
+
+        let endpoint =
+            receiver_endpoint(&directory, &rk.short_id(), ohttp.clone(), rk.clone(), Some(exp))
+                .unwrap();
+        assert_eq!(
+            endpoint.as_str(),
+            "https://directory.example.com/YJ9H5AA27CGVS#EX1C4UC6ES\
+             -OH1QYP87E2AVMDKXDTU6R25WCPQ5ZUF02XHNPA65JMD8ZA2W4YRQN6UUWG\
+             -RK1QG2RH36X9ZWRK7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG"
+        );
+
+        let endpoint = receiver_endpoint(&directory, &rk.short_id(), ohttp, rk, None).unwrap();
+        assert!(!endpoint.fragment().unwrap().contains("EX1"));
+    }
+
     #[test]
     fn test_v2_mutable_receiver_state_closures() {
         let mut call_count = 0;
@@ -1301,6 +1358,40 @@ pub mod test {
         }
     }
 
+    #[test]
+    fn far_future_expiry_is_capped() {
+        use crate::uri::url_ext::max_exp;
+        use crate::uri::UrlExt;
+
+        let noop_persister = NoopSessionPersister::default();
+        let session = Receiver::create_session(
+            SHARED_CONTEXT.address.clone(),
+            SHARED_CONTEXT.directory.clone(),
+            SHARED_CONTEXT.ohttp_keys.clone(),
+            Some(Duration::from_secs(u32::MAX as u64 * 2)),
+        )
+        .save(&noop_persister)
+        .expect("Noop persister shouldn't fail");
+        assert_eq!(session.context.expiry, max_exp());
+        assert_eq!(session.pj_uri().extras.endpoint.exp().unwrap(), max_exp());
+    }
+
+    #[test]
+    fn stored_far_future_expiry_is_capped() {
+        use crate::uri::url_ext::max_exp;
+        use crate::uri::UrlExt;
+
+        let mut context = SHARED_CONTEXT.clone();
+        context.expiry = max_exp() + Duration::from_secs(1);
+        let serialized = serde_json::to_string(&context).expect("serialization should succeed");
+        let deserialized: SessionContext =
+            serde_json::from_str(&serialized).expect("deserialization should succeed");
+        assert_eq!(deserialized.expiry, max_exp());
+
+        let uri = Receiver { state: Initialized { context } }.pj_uri();
+        assert_eq!(uri.extras.endpoint.exp().unwrap(), max_exp());
+    }
+
     #[test]
     fn test_v2_pj_uri() {
         let uri = Receiver { state: Initialized { context: SHARED_CONTEXT.clone() } }.pj_uri();
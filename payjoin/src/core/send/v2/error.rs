@@ -18,6 +18,7 @@ pub(crate) enum InternalCreateRequestError {
     OhttpEncapsulation(crate::ohttp::OhttpEncapsulationError),
     ParseReceiverPubkey(ParseReceiverPubkeyParamError),
     MissingOhttpConfig,
+    DoubleEncodedFragment,
     Expired(std::time::SystemTime),
 }
 
@@ -32,6 +33,10 @@ impl fmt::Display for CreateRequestError {
             ParseReceiverPubkey(e) => write!(f, "cannot parse receiver public key: {e}"),
             MissingOhttpConfig =>
                 write!(f, "no ohttp configuration with which to make a v2 request available"),
+            DoubleEncodedFragment => write!(
+                f,
+                "the endpoint has %23 in its path but no fragment, was it percent-encoded twice?"
+            ),
             Expired(expiry) => write!(f, "session expired at {expiry:?}"),
         }
     }
@@ -47,6 +52,7 @@ impl std::error::Error for CreateRequestError {
             OhttpEncapsulation(error) => Some(error),
             ParseReceiverPubkey(error) => Some(error),
             MissingOhttpConfig => None,
+            DoubleEncodedFragment => None,
             Expired(_) => None,
         }
     }
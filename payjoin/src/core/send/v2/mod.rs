@@ -28,7 +28,6 @@
 //! Note: Even fresh requests may be linkable via metadata (e.g. client IP, request timing),
 //! but request reuse makes correlation trivial for the relay.
 
-use bitcoin::hashes::{sha256, Hash};
 pub use error::{CreateRequestError, EncapsulationError};
 use error::{InternalCreateRequestError, InternalEncapsulationError};
 use ohttp::ClientResponse;
@@ -44,7 +43,7 @@ use crate::persist::{
     MaybeBadInitInputsTransition, MaybeFatalTransition, MaybeSuccessTransitionWithNoResults,
 };
 use crate::send::v2::session::InternalReplayError;
-use crate::uri::{ShortId, UrlExt};
+use crate::uri::UrlExt;
 use crate::{HpkeKeyPair, HpkePublicKey, IntoUrl, OhttpKeys, PjUri, Request};
 
 mod error;
@@ -266,6 +265,9 @@ impl Sender<WithReplyKey> {
                 return Err(InternalCreateRequestError::Expired(expiry).into());
             }
         }
+        if crate::uri::url_ext::has_double_encoded_fragment(&self.endpoint) {
+            return Err(InternalCreateRequestError::DoubleEncodedFragment.into());
+        }
 
         let mut ohttp_keys =
             self.endpoint().ohttp().map_err(|_| InternalCreateRequestError::MissingOhttpConfig)?;
@@ -423,11 +425,9 @@ impl Sender<V2GetContext> {
     ) -> Result<(Request, ohttp::ClientResponse), CreateRequestError> {
         let base_url = self.endpoint.clone();
 
-        // TODO unify with receiver's fn short_id_from_pubkey
-        let hash = sha256::Hash::hash(&self.hpke_ctx.reply_pair.public_key().to_compressed_bytes());
-        let mailbox: ShortId = hash.into();
+        let mailbox = self.hpke_ctx.reply_pair.public_key().short_id();
         let url = base_url
-            .join(&mailbox.to_string())
+            .join(&mailbox.subdirectory_path())
             .map_err(|e| InternalCreateRequestError::Url(e.into()))?;
         let body = encrypt_message_a(
             Vec::new(),
@@ -550,7 +550,7 @@ mod test {
                 reply_key: HpkeKeyPair::gen_keypair().0,
             },
         };
-        sender.endpoint.set_exp(SystemTime::now() + Duration::from_secs(60));
+        sender.endpoint.set_exp(SystemTime::now() + Duration::from_secs(60)).unwrap();
         sender.endpoint.set_receiver_pubkey(HpkeKeyPair::gen_keypair().1);
         sender.endpoint.set_ohttp(OhttpKeys(
             ohttp::KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).expect("valid key config"),
@@ -593,7 +593,7 @@ mod test {
         let expected_error = "cannot parse receiver public key: receiver public key is missing";
         let mut sender = create_sender_context()?;
         sender.endpoint.set_fragment(Some(""));
-        sender.endpoint.set_exp(SystemTime::now() + Duration::from_secs(60));
+        sender.endpoint.set_exp(SystemTime::now() + Duration::from_secs(60)).unwrap();
         sender.endpoint.set_ohttp(OhttpKeys(
             ohttp::KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).expect("valid key config"),
         ));
@@ -608,12 +608,38 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_extract_v2_fails_double_encoded_fragment() -> Result<(), BoxError> {
+        use crate::UriExt;
+
+        let uri = crate::Uri::try_from(
+            "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?pj=HTTPS://EXAMPLE.COM/\
+             %2523EX1XPK8Y6Q-OH1QYP87E2AVMDKXDTU6R25WCPQ5ZUF02XHNPA65JMD8ZA2W4YRQN6UUWG\
+             -RK1QG2RH36X9ZWRK7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG",
+        )?
+        .assume_checked()
+        .check_pj_supported()
+        .map_err(|_| "expected a payjoin URI")?;
+        assert_eq!(uri.extras.endpoint().fragment(), None);
+
+        let mut sender = create_sender_context()?;
+        sender.endpoint = uri.extras.endpoint().clone();
+        match sender.create_v2_post_request(EXAMPLE_URL.clone()) {
+            Ok(_) => panic!("Expected error, got success"),
+            Err(error) => assert_eq!(
+                error.to_string(),
+                "the endpoint has %23 in its path but no fragment, was it percent-encoded twice?"
+            ),
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_extract_v2_fails_missing_ohttp_config() -> Result<(), BoxError> {
         let expected_error = "no ohttp configuration with which to make a v2 request available";
         let mut sender = create_sender_context()?;
         sender.endpoint.set_fragment(Some(""));
-        sender.endpoint.set_exp(SystemTime::now() + Duration::from_secs(60));
+        sender.endpoint.set_exp(SystemTime::now() + Duration::from_secs(60)).unwrap();
         sender.endpoint.set_receiver_pubkey(HpkeKeyPair::gen_keypair().1);
         let ohttp_relay = EXAMPLE_URL.clone();
         let result = sender.create_v2_post_request(ohttp_relay);
@@ -631,7 +657,7 @@ mod test {
         let expected_error = "session expired at SystemTime";
         let mut sender = create_sender_context()?;
         let exp_time = std::time::SystemTime::now();
-        sender.endpoint.set_exp(exp_time);
+        sender.endpoint.set_exp(exp_time).unwrap();
         let ohttp_relay = EXAMPLE_URL.clone();
         let result = sender.create_v2_post_request(ohttp_relay);
         assert!(result.is_err(), "Extract v2 expected expiry error, but it succeeded");
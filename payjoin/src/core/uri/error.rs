@@ -3,30 +3,170 @@ use url::ParseError;
 #[derive(Debug)]
 pub struct PjParseError(pub(crate) InternalPjParseError);
 
-#[derive(Debug, PartialEq, Eq)]
+/// The class of failure behind a [`PjParseError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PjParseErrorKind {
+    /// The `pjos` parameter is not `0` or `1`
+    BadPjOs,
+    /// A payjoin parameter appears more than once
+    DuplicateParams,
+    /// The `pj` parameter is missing
+    MissingEndpoint,
+    /// A payjoin parameter is not valid UTF-8
+    NotUtf8,
+    /// The endpoint is not a valid URL
+    BadEndpoint,
+    /// The endpoint does not use a secure transport
+    UnsecureEndpoint,
+    /// The endpoint contains reserved characters that should have been percent-encoded
+    UnencodedEndpoint,
+    /// The string is not a valid BIP21 URI
+    InvalidUri,
+    /// The URI is a valid BIP21 URI without a payjoin endpoint
+    NotPayjoin,
+    /// The URI has a `req-` parameter which payjoin does not know, so it must not be used
+    UnknownRequiredParam,
+    /// The requested amount is more than the total bitcoin supply
+    AmountExceedsMaxMoney,
+    /// An amount was required but the URI does not request one
+    MissingAmount,
+    /// The endpoint's exp parameter is in the past
+    #[cfg(feature = "v2")]
+    ExpiredEndpoint,
+    /// The endpoint's exp parameter is present but malformed
+    #[cfg(feature = "v2")]
+    InvalidExp,
+}
+
+impl PjParseError {
+    /// Classify this error without depending on its internal representation.
+    pub fn kind(&self) -> PjParseErrorKind {
+        use InternalPjParseError::*;
+        match &self.0 {
+            BadPjOs => PjParseErrorKind::BadPjOs,
+            DuplicateParams(_) => PjParseErrorKind::DuplicateParams,
+            MissingEndpoint => PjParseErrorKind::MissingEndpoint,
+            NotUtf8 { .. } => PjParseErrorKind::NotUtf8,
+            BadEndpoint(_) => PjParseErrorKind::BadEndpoint,
+            UnsecureEndpoint => PjParseErrorKind::UnsecureEndpoint,
+            UnencodedEndpoint => PjParseErrorKind::UnencodedEndpoint,
+            InvalidUri(_) => PjParseErrorKind::InvalidUri,
+            NotPayjoin => PjParseErrorKind::NotPayjoin,
+            UnknownRequiredParam(_) => PjParseErrorKind::UnknownRequiredParam,
+            AmountExceedsMaxMoney(_) => PjParseErrorKind::AmountExceedsMaxMoney,
+            MissingAmount => PjParseErrorKind::MissingAmount,
+            #[cfg(feature = "v2")]
+            ExpiredEndpoint(_) => PjParseErrorKind::ExpiredEndpoint,
+            #[cfg(feature = "v2")]
+            InvalidExp(_) => PjParseErrorKind::InvalidExp,
+        }
+    }
+
+    /// The key of the parameter which appeared more than once, if that caused this error.
+    pub fn duplicate_param(&self) -> Option<&'static str> {
+        match &self.0 {
+            InternalPjParseError::DuplicateParams(param) => Some(param),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub(crate) enum InternalPjParseError {
     BadPjOs,
     DuplicateParams(&'static str),
     MissingEndpoint,
-    NotUtf8,
+    NotUtf8 {
+        key: &'static str,
+    },
     BadEndpoint(BadEndpointError),
     UnsecureEndpoint,
+    UnencodedEndpoint,
+    InvalidUri(bitcoin_uri::de::UriError),
+    NotPayjoin,
+    UnknownRequiredParam(String),
+    AmountExceedsMaxMoney(bitcoin::Amount),
+    MissingAmount,
+    #[cfg(feature = "v2")]
+    ExpiredEndpoint(std::time::SystemTime),
+    #[cfg(feature = "v2")]
+    InvalidExp(crate::uri::url_ext::ParseExpParamError),
 }
 
 #[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum BadEndpointError {
     UrlParse(ParseError),
+    CredentialsInUrl,
     #[cfg(feature = "v2")]
     LowercaseFragment,
+    #[cfg(feature = "v2")]
+    AmbiguousFragmentDelimiter,
+    #[cfg(feature = "v2")]
+    FragmentTooLong(usize),
+    #[cfg(feature = "v2")]
+    DuplicateFragmentParam(bitcoin::bech32::Hrp),
+    #[cfg(feature = "v2")]
+    EmptyFragmentSegment,
+    #[cfg(feature = "v2")]
+    FragmentNotSorted,
+    #[cfg(feature = "v2")]
+    Fragment(crate::uri::url_ext::ParseFragmentError),
 }
 
 impl std::fmt::Display for BadEndpointError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            BadEndpointError::UrlParse(e) => write!(f, "Invalid URL: {e:?}"),
+            BadEndpointError::UrlParse(e) => write!(f, "Invalid URL: {e}"),
+            BadEndpointError::CredentialsInUrl =>
+                write!(f, "The URL must not contain a username or password"),
             #[cfg(feature = "v2")]
             BadEndpointError::LowercaseFragment =>
                 write!(f, "Some or all of the fragment is lowercase"),
+            #[cfg(feature = "v2")]
+            BadEndpointError::AmbiguousFragmentDelimiter =>
+                write!(f, "The fragment uses both + and - as delimiters"),
+            #[cfg(feature = "v2")]
+            BadEndpointError::FragmentTooLong(len) => write!(
+                f,
+                "The fragment is {len} bytes, more than the maximum of {}",
+                crate::uri::url_ext::MAX_FRAGMENT_LENGTH
+            ),
+            #[cfg(feature = "v2")]
+            BadEndpointError::DuplicateFragmentParam(hrp) =>
+                write!(f, "The fragment has more than one {hrp} parameter"),
+            #[cfg(feature = "v2")]
+            BadEndpointError::EmptyFragmentSegment =>
+                write!(f, "The fragment has a leading, trailing or repeated delimiter"),
+            #[cfg(feature = "v2")]
+            BadEndpointError::FragmentNotSorted =>
+                write!(f, "The fragment parameters are not sorted lexicographically"),
+            #[cfg(feature = "v2")]
+            BadEndpointError::Fragment(e) => write!(f, "Invalid fragment: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BadEndpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BadEndpointError::UrlParse(e) => Some(e),
+            BadEndpointError::CredentialsInUrl => None,
+            #[cfg(feature = "v2")]
+            BadEndpointError::LowercaseFragment => None,
+            #[cfg(feature = "v2")]
+            BadEndpointError::AmbiguousFragmentDelimiter => None,
+            #[cfg(feature = "v2")]
+            BadEndpointError::FragmentTooLong(_) => None,
+            #[cfg(feature = "v2")]
+            BadEndpointError::DuplicateFragmentParam(_) => None,
+            #[cfg(feature = "v2")]
+            BadEndpointError::EmptyFragmentSegment => None,
+            #[cfg(feature = "v2")]
+            BadEndpointError::FragmentNotSorted => None,
+            #[cfg(feature = "v2")]
+            BadEndpointError::Fragment(e) => Some(e),
         }
     }
 }
@@ -35,6 +175,10 @@ impl From<InternalPjParseError> for PjParseError {
     fn from(value: InternalPjParseError) -> Self { PjParseError(value) }
 }
 
+impl From<BadEndpointError> for PjParseError {
+    fn from(value: BadEndpointError) -> Self { InternalPjParseError::BadEndpoint(value).into() }
+}
+
 impl std::fmt::Display for PjParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use InternalPjParseError::*;
@@ -44,11 +188,117 @@ impl std::fmt::Display for PjParseError {
                 write!(f, "Multiple instances of parameter '{param}'")
             }
             MissingEndpoint => write!(f, "Missing payjoin endpoint"),
-            NotUtf8 => write!(f, "Endpoint is not valid UTF-8"),
-            BadEndpoint(e) => write!(f, "Endpoint is not valid: {e:?}"),
+            NotUtf8 { key } => write!(f, "Parameter '{key}' is not valid UTF-8"),
+            BadEndpoint(e) => write!(f, "Endpoint is not valid: {e}"),
             UnsecureEndpoint => {
                 write!(f, "Endpoint scheme is not secure (https or onion)")
             }
+            UnencodedEndpoint => {
+                write!(f, "Endpoint contains unencoded reserved characters")
+            }
+            InvalidUri(e) => write!(f, "Invalid BIP21 URI: {e}"),
+            NotPayjoin => write!(f, "URI does not support payjoin"),
+            UnknownRequiredParam(key) => write!(f, "Unknown required parameter '{key}'"),
+            AmountExceedsMaxMoney(amount) =>
+                write!(f, "Amount {amount} is more than the total bitcoin supply"),
+            MissingAmount => write!(f, "URI does not request an amount"),
+            #[cfg(feature = "v2")]
+            ExpiredEndpoint(exp) => write!(f, "Endpoint expired at {exp:?}"),
+            #[cfg(feature = "v2")]
+            InvalidExp(e) => write!(f, "Endpoint expiry is not valid: {e}"),
         }
     }
 }
+
+impl std::error::Error for PjParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use InternalPjParseError::*;
+        match &self.0 {
+            BadEndpoint(e) => Some(e),
+            InvalidUri(e) => Some(e),
+            BadPjOs
+            | DuplicateParams(_)
+            | MissingEndpoint
+            | NotUtf8 { .. }
+            | UnsecureEndpoint
+            | UnencodedEndpoint
+            | NotPayjoin
+            | UnknownRequiredParam(_)
+            | AmountExceedsMaxMoney(_)
+            | MissingAmount => None,
+            #[cfg(feature = "v2")]
+            ExpiredEndpoint(_) => None,
+            #[cfg(feature = "v2")]
+            InvalidExp(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+
+    #[test]
+    fn test_source_chain() {
+        let error = PjParseError(InternalPjParseError::BadEndpoint(BadEndpointError::UrlParse(
+            ParseError::EmptyHost,
+        )));
+        let bad_endpoint = error
+            .source()
+            .and_then(|e| e.downcast_ref::<BadEndpointError>())
+            .expect("source should be the bad endpoint error");
+        assert_eq!(bad_endpoint, &BadEndpointError::UrlParse(ParseError::EmptyHost));
+        let url_error = bad_endpoint
+            .source()
+            .and_then(|e| e.downcast_ref::<ParseError>())
+            .expect("source should be the url parse error");
+        assert_eq!(url_error, &ParseError::EmptyHost);
+
+        assert!(PjParseError(InternalPjParseError::MissingEndpoint).source().is_none());
+    }
+
+    #[test]
+    fn test_from_bad_endpoint() {
+        fn parse_endpoint(endpoint: &str) -> Result<url::Url, PjParseError> {
+            let url = url::Url::parse(endpoint).map_err(BadEndpointError::UrlParse)?;
+            if url.username() != "" {
+                return Err(BadEndpointError::CredentialsInUrl.into());
+            }
+            Ok(url)
+        }
+
+        assert!(parse_endpoint("https://example.com").is_ok());
+        let error = parse_endpoint("https://").unwrap_err();
+        assert!(matches!(
+            error,
+            PjParseError(InternalPjParseError::BadEndpoint(BadEndpointError::UrlParse(
+                ParseError::EmptyHost
+            )))
+        ));
+        let error = parse_endpoint("https://user@example.com").unwrap_err();
+        assert_eq!(error.kind(), PjParseErrorKind::BadEndpoint);
+        assert!(matches!(
+            error,
+            PjParseError(InternalPjParseError::BadEndpoint(BadEndpointError::CredentialsInUrl))
+        ));
+    }
+
+    #[test]
+    fn test_url_parse_failure_source() {
+        let error = match crate::uri::Uri::try_from(
+            "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?pj=https://",
+        ) {
+            Err(bitcoin_uri::de::Error::Extras(e)) => e,
+            other => panic!("expected an endpoint parse error, got {other:?}"),
+        };
+        let bad_endpoint = error.source().expect("source should be the bad endpoint error");
+        assert_eq!(bad_endpoint.to_string(), "Invalid URL: empty host");
+        let url_error = bad_endpoint
+            .source()
+            .and_then(|e| e.downcast_ref::<ParseError>())
+            .expect("source of the bad endpoint should be the url parse error");
+        assert_eq!(url_error, &ParseError::EmptyHost);
+    }
+}
@@ -0,0 +1,40 @@
+//! The byte format of the exp fragment parameter
+//!
+//! BIP 77 encodes the expiry as a bitcoin consensus encoded, i.e. little endian, u32 timestamp
+//! in seconds since the unix epoch. Keeping the format here means a wider timestamp in a later
+//! revision of the spec only touches this module.
+
+/// The length of an encoded exp payload, before bech32 encoding
+pub(crate) const EXP_BYTES: usize = 4;
+
+/// Encode a timestamp as an exp payload
+pub(crate) fn encode(timestamp: u32) -> [u8; EXP_BYTES] { timestamp.to_le_bytes() }
+
+/// Decode an exp payload into a timestamp
+///
+/// Returns the payload length as the error if it is not exactly [`EXP_BYTES`], so trailing bytes
+/// are rejected rather than ignored.
+pub(crate) fn decode(bytes: &[u8]) -> Result<u32, usize> {
+    let bytes: [u8; EXP_BYTES] = bytes.try_into().map_err(|_| bytes.len())?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_exp() {
+        let bytes = encode(1_720_547_781);
+        assert_eq!(bytes, [0xc5, 0x79, 0x8d, 0x66]);
+        assert_eq!(decode(&bytes), Ok(1_720_547_781));
+        assert_eq!(decode(&encode(u32::MAX)), Ok(u32::MAX));
+    }
+
+    #[test]
+    fn test_exp_wrong_length() {
+        assert_eq!(decode(&[0xc5, 0x79, 0x8d, 0x66, 0x00]), Err(5));
+        assert_eq!(decode(&[0xc5, 0x79, 0x8d]), Err(3));
+        assert_eq!(decode(&[]), Err(0));
+    }
+}
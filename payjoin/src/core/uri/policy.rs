@@ -0,0 +1,154 @@
+//! Policies deciding which payjoin endpoints are secure enough to use
+
+use bitcoin::address::NetworkUnchecked;
+use url::Url;
+
+use super::{
+    check_endpoint_encoding, from_uri_error, DeserializationState, MaybePayjoinExtras,
+    PjParseError, Uri, UriPolicyExt,
+};
+use crate::uri::error::InternalPjParseError;
+
+/// Decides whether a payjoin endpoint protects the request in transit
+///
+/// [`Uri::try_from`] always applies [`DefaultEndpointPolicy`]. Deployments with other guarantees,
+/// e.g. plain http to a trusted reverse proxy, can parse with their own policy through
+/// [`UriPolicyExt::try_from_with_policy`].
+pub trait SecureEndpointPolicy {
+    fn check(&self, endpoint: &Url) -> Result<(), UnsecureEndpoint>;
+}
+
+/// The policy applied when parsing a [`Uri`]
+///
+/// It accepts https endpoints, and plain http only where the transport is otherwise encrypted,
+/// such as onion services.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultEndpointPolicy;
+
+impl SecureEndpointPolicy for DefaultEndpointPolicy {
+    fn check(&self, endpoint: &Url) -> Result<(), UnsecureEndpoint> {
+        super::check_endpoint_security(endpoint).map_err(|_| UnsecureEndpoint)
+    }
+}
+
+/// A [`SecureEndpointPolicy`] rejected the endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsecureEndpoint;
+
+impl std::fmt::Display for UnsecureEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "endpoint is not secure")
+    }
+}
+
+impl std::error::Error for UnsecureEndpoint {}
+
+impl<'a> UriPolicyExt<'a> for Uri<'a, NetworkUnchecked> {
+    fn try_from_with_policy<P: SecureEndpointPolicy + ?Sized>(
+        s: &'a str,
+        policy: &P,
+    ) -> Result<Self, PjParseError> {
+        check_endpoint_encoding(s)?;
+        let uri = bitcoin_uri::Uri::<'a, NetworkUnchecked, UncheckedExtras>::try_from(s)
+            .map_err(from_uri_error)?;
+        let mut extras = uri.extras.0;
+        extras.mark_query_checked();
+        if let MaybePayjoinExtras::Supported(extras) = &extras {
+            policy.check(&extras.endpoint).map_err(|_| InternalPjParseError::UnsecureEndpoint)?;
+        }
+
+        let mut checked = bitcoin_uri::Uri::with_extras(uri.address, extras);
+        checked.amount = uri.amount;
+        checked.label = uri.label;
+        checked.message = uri.message;
+        Ok(checked)
+    }
+}
+
+/// Payjoin extras parsed without any endpoint security check
+struct UncheckedExtras(MaybePayjoinExtras);
+
+impl bitcoin_uri::de::DeserializationError for UncheckedExtras {
+    type Error = PjParseError;
+}
+
+impl bitcoin_uri::de::DeserializeParams<'_> for UncheckedExtras {
+    type DeserializationState = UncheckedDeserializationState;
+}
+
+#[derive(Default)]
+struct UncheckedDeserializationState(DeserializationState);
+
+impl<'de> bitcoin_uri::de::DeserializationState<'de> for UncheckedDeserializationState {
+    type Value = UncheckedExtras;
+
+    fn is_param_known(&self, param: &str) -> bool { self.0.is_param_known(param) }
+
+    fn deserialize_temp(
+        &mut self,
+        key: &str,
+        value: bitcoin_uri::Param<'_>,
+    ) -> Result<bitcoin_uri::de::ParamKind, PjParseError> {
+        self.0.deserialize_temp(key, value)
+    }
+
+    fn finalize(self) -> Result<UncheckedExtras, PjParseError> {
+        self.0.finalize_unchecked().map(UncheckedExtras)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uri::error::PjParseErrorKind;
+    use crate::uri::UriExt;
+
+    /// Also accepts plain http to loopback and private network hosts, e.g. behind a reverse proxy
+    struct PrivateNetworkPolicy;
+
+    impl SecureEndpointPolicy for PrivateNetworkPolicy {
+        fn check(&self, endpoint: &Url) -> Result<(), UnsecureEndpoint> {
+            match endpoint.host() {
+                Some(url::Host::Ipv4(ip))
+                    if endpoint.scheme() == "http" && (ip.is_loopback() || ip.is_private()) =>
+                    Ok(()),
+                Some(url::Host::Ipv6(ip)) if endpoint.scheme() == "http" && ip.is_loopback() =>
+                    Ok(()),
+                _ => DefaultEndpointPolicy.check(endpoint),
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        let uri = "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?amount=0.01&pj=http://10.0.0.1/pj";
+
+        let parsed = Uri::try_from_with_policy(uri, &PrivateNetworkPolicy).unwrap();
+        assert!(parsed.extras.pj_is_supported());
+        assert_eq!(parsed.amount, Some(bitcoin::Amount::from_sat(1_000_000)));
+        assert!(parsed.assume_checked().to_string().ends_with("pj=HTTP://10.0.0.1/pj"));
+
+        match Uri::try_from(uri) {
+            Err(bitcoin_uri::de::Error::Extras(e)) =>
+                assert_eq!(e.kind(), PjParseErrorKind::UnsecureEndpoint),
+            other => panic!("expected the default policy to reject plain http, got {other:?}"),
+        }
+        let error = Uri::try_from_with_policy(uri, &DefaultEndpointPolicy).unwrap_err();
+        assert_eq!(error.kind(), PjParseErrorKind::UnsecureEndpoint);
+
+        let clearnet = "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?pj=http://example.com";
+        assert!(Uri::try_from_with_policy(clearnet, &PrivateNetworkPolicy).is_err());
+        let https = "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?pj=https://example.com";
+        assert!(Uri::try_from_with_policy(https, &PrivateNetworkPolicy).is_ok());
+
+        for loopback in ["http://127.0.0.1:8080/pj", "http://[::1]:8080/pj"] {
+            let uri = format!("bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?pj={loopback}");
+            let parsed = Uri::try_from_with_policy(&uri, &PrivateNetworkPolicy).unwrap();
+            let pjuri = parsed.assume_checked().check_pj_supported().unwrap();
+            assert_eq!(pjuri.extras.endpoint().as_str(), loopback);
+            // Test utilities accept loopback endpoints by default for local integration tests
+            #[cfg(not(feature = "_test-utils"))]
+            assert!(Uri::try_from(uri.as_str()).is_err());
+        }
+    }
+}
@@ -1,121 +1,423 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::str::FromStr;
 
 use bitcoin::bech32::Hrp;
-use bitcoin::consensus::encode::Decodable;
-use bitcoin::consensus::Encodable;
+use bitcoin::key::constants::PUBLIC_KEY_SIZE;
 use url::Url;
 
 use super::error::BadEndpointError;
+use super::exp;
 use crate::hpke::HpkePublicKey;
 use crate::ohttp::OhttpKeys;
 
+/// The bech32 HRP of the receiver public key fragment parameter
+pub(crate) const RK_HRP: Hrp = Hrp::parse_unchecked("RK");
+/// The bech32 HRP of the ohttp keys fragment parameter
+pub(crate) const OH_HRP: Hrp = Hrp::parse_unchecked("OH");
+/// The bech32 HRP of the expiry fragment parameter
+pub(crate) const EX_HRP: Hrp = Hrp::parse_unchecked("EX");
+
 /// Parse and set fragment parameters from `&pj=` URI parameter URLs
-pub(crate) trait UrlExt {
+///
+/// Parameters without a typed accessor, e.g. experimental ones, are read and set by their bech32
+/// HRP with [`UrlExt::fragment_param`] and [`UrlExt::set_fragment_param`].
+pub trait UrlExt {
+    fn fragment_param(&self, hrp: Hrp) -> Result<Option<&str>, ParseFragmentError>;
+    fn set_fragment_param(
+        &mut self,
+        hrp: Hrp,
+        param: Option<&str>,
+    ) -> Result<(), SetFragmentParamError>;
     fn receiver_pubkey(&self) -> Result<HpkePublicKey, ParseReceiverPubkeyParamError>;
     fn set_receiver_pubkey(&mut self, exp: HpkePublicKey);
+    fn rotate_receiver_pubkey(&mut self, new: HpkePublicKey) -> Option<HpkePublicKey>;
     fn ohttp(&self) -> Result<OhttpKeys, ParseOhttpKeysParamError>;
     fn set_ohttp(&mut self, ohttp: OhttpKeys);
+    fn exp_timestamp(&self) -> Result<u32, ParseExpParamError>;
     fn exp(&self) -> Result<std::time::SystemTime, ParseExpParamError>;
-    fn set_exp(&mut self, exp: std::time::SystemTime);
+    fn exp_opt(&self) -> Result<Option<std::time::SystemTime>, ParseExpParamError>;
+    #[cfg(feature = "chrono")]
+    fn exp_rfc3339(&self) -> Result<Option<String>, ParseExpParamError>;
+    fn set_exp(&mut self, exp: std::time::SystemTime) -> Result<(), SetExpError>;
+    fn set_exp_timestamp(&mut self, timestamp: u32);
+    fn clear_exp(&mut self);
+    fn is_expired(&self, now: std::time::SystemTime) -> Result<bool, ParseExpParamError>;
+    fn is_expired_at_timestamp(&self, now: u32) -> Result<bool, ParseExpParamError>;
+    fn normalize_fragment(&mut self) -> Result<(), ParseFragmentError>;
+    fn canonicalize_fragment(&mut self) -> Result<(), ParseFragmentError>;
+    fn v2_params(&self) -> Result<V2Params, ParseV2ParamsError>;
+    fn fragment_params(&self) -> Result<Vec<(Hrp, &str)>, ParseFragmentError>;
+    fn merge_fragment_from(&mut self, other: &Url) -> Result<(), ParseFragmentError>;
+    fn with_v2_params(
+        base: Url,
+        ohttp_keys: OhttpKeys,
+        receiver_pubkey: HpkePublicKey,
+        exp: Option<std::time::SystemTime>,
+    ) -> Result<Url, SetExpError>;
+}
+
+/// The parameters a v2 sender needs from the payjoin endpoint's fragment
+#[derive(Debug, Clone)]
+pub struct V2Params {
+    /// The keys to encapsulate requests to the directory with
+    pub ohttp_keys: OhttpKeys,
+    /// The key to encrypt the original PSBT to the receiver with
+    pub receiver_pubkey: HpkePublicKey,
+    /// `None` when the fragment has no exp parameter
+    pub exp: Option<std::time::SystemTime>,
 }
 
 impl UrlExt for Url {
+    /// Retrieve the fragment parameter with the given bech32 HRP, if present
+    fn fragment_param(&self, hrp: Hrp) -> Result<Option<&str>, ParseFragmentError> {
+        get_param(self, hrp)
+    }
+
+    /// Set the fragment parameter with the given bech32 HRP, or remove it if `param` is `None`
+    ///
+    /// `param` is the entire bech32 encoded parameter, including its HRP, which must be `hrp`.
+    /// It must be uppercase, since lowercase fragments are rejected when parsing the endpoint.
+    fn set_fragment_param(
+        &mut self,
+        hrp: Hrp,
+        param: Option<&str>,
+    ) -> Result<(), SetFragmentParamError> {
+        // Otherwise the param would be stored in the slot of another HRP, e.g. a second EX param
+        // in place of the OH param
+        if param.map_or(false, |param| param_hrp(param) != Some(hrp)) {
+            return Err(SetFragmentParamError::HrpMismatch(hrp));
+        }
+        set_param(self, hrp, param)
+    }
+
     /// Retrieve the receiver's public key from the URL fragment
     fn receiver_pubkey(&self) -> Result<HpkePublicKey, ParseReceiverPubkeyParamError> {
-        let value = get_param(self, "RK1")
+        let value = self
+            .fragment_param(RK_HRP)
             .map_err(ParseReceiverPubkeyParamError::InvalidFragment)?
             .ok_or(ParseReceiverPubkeyParamError::MissingPubkey)?;
 
-        let (hrp, bytes) = crate::bech32::nochecksum::decode(value)
-            .map_err(ParseReceiverPubkeyParamError::DecodeBech32)?;
-
-        let rk_hrp: Hrp = Hrp::parse("RK").unwrap();
-        if hrp != rk_hrp {
-            return Err(ParseReceiverPubkeyParamError::InvalidHrp(hrp));
-        }
-
-        HpkePublicKey::from_compressed_bytes(&bytes[..])
-            .map_err(ParseReceiverPubkeyParamError::InvalidPubkey)
+        decode_receiver_pubkey(value)
     }
 
     /// Set the receiver's public key in the URL fragment
+    ///
+    /// A fragment with invalid characters or ambiguous delimiters is replaced.
     fn set_receiver_pubkey(&mut self, pubkey: HpkePublicKey) {
-        let rk_hrp: Hrp = Hrp::parse("RK").unwrap();
+        set_typed_param(self, RK_HRP, &pubkey.to_string())
+    }
 
-        set_param(
-            self,
-            &crate::bech32::nochecksum::encode(rk_hrp, &pubkey.to_compressed_bytes())
-                .expect("encoding compressed pubkey bytes should never fail"),
-        )
+    /// Replace the receiver's public key in the URL fragment
+    ///
+    /// Returns the previous key if one was present and valid, e.g. to log the rotation.
+    fn rotate_receiver_pubkey(&mut self, new: HpkePublicKey) -> Option<HpkePublicKey> {
+        let previous = self.receiver_pubkey().ok();
+        self.set_receiver_pubkey(new);
+        previous
     }
 
     /// Retrieve the ohttp parameter from the URL fragment
     fn ohttp(&self) -> Result<OhttpKeys, ParseOhttpKeysParamError> {
-        let value = get_param(self, "OH1")
+        let value = self
+            .fragment_param(OH_HRP)
             .map_err(ParseOhttpKeysParamError::InvalidFragment)?
             .ok_or(ParseOhttpKeysParamError::MissingOhttpKeys)?;
         OhttpKeys::from_str(value).map_err(ParseOhttpKeysParamError::InvalidOhttpKeys)
     }
 
     /// Set the ohttp parameter in the URL fragment
-    fn set_ohttp(&mut self, ohttp: OhttpKeys) { set_param(self, &ohttp.to_string()) }
-
-    /// Retrieve the exp parameter from the URL fragment
-    fn exp(&self) -> Result<std::time::SystemTime, ParseExpParamError> {
-        let value = get_param(self, "EX1")
+    ///
+    /// A fragment with invalid characters or ambiguous delimiters is replaced.
+    fn set_ohttp(&mut self, ohttp: OhttpKeys) { set_typed_param(self, OH_HRP, &ohttp.to_string()) }
+
+    /// Retrieve the exp parameter from the URL fragment as seconds since the unix epoch
+    fn exp_timestamp(&self) -> Result<u32, ParseExpParamError> {
+        let value = self
+            .fragment_param(EX_HRP)
             .map_err(ParseExpParamError::InvalidFragment)?
             .ok_or(ParseExpParamError::MissingExp)?;
 
         let (hrp, bytes) =
             crate::bech32::nochecksum::decode(value).map_err(ParseExpParamError::DecodeBech32)?;
 
-        let ex_hrp: Hrp = Hrp::parse("EX").unwrap();
-        if hrp != ex_hrp {
-            return Err(ParseExpParamError::InvalidHrp(hrp));
+        if hrp != EX_HRP {
+            return Err(ParseExpParamError::InvalidHrp {
+                expected: Box::new(EX_HRP),
+                actual: Box::new(hrp),
+            });
         }
 
-        u32::consensus_decode(&mut &bytes[..])
-            .map(|timestamp| {
-                std::time::UNIX_EPOCH + std::time::Duration::from_secs(timestamp as u64)
-            })
-            .map_err(ParseExpParamError::InvalidExp)
+        exp::decode(&bytes).map_err(ParseExpParamError::InvalidExp)
+    }
+
+    /// Retrieve the exp parameter from the URL fragment
+    fn exp(&self) -> Result<std::time::SystemTime, ParseExpParamError> {
+        self.exp_timestamp().map(|timestamp| {
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(timestamp as u64)
+        })
+    }
+
+    /// Retrieve the exp parameter from the URL fragment, or `None` if there is none
+    ///
+    /// Unlike [`UrlExt::exp`] a missing exp is not an error, only a malformed one is.
+    fn exp_opt(&self) -> Result<Option<std::time::SystemTime>, ParseExpParamError> {
+        match self.exp() {
+            Ok(exp) => Ok(Some(exp)),
+            Err(ParseExpParamError::MissingExp) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Retrieve the exp parameter from the URL fragment as an RFC 3339 UTC timestamp
+    ///
+    /// Returns `None` if the fragment has no exp parameter.
+    #[cfg(feature = "chrono")]
+    fn exp_rfc3339(&self) -> Result<Option<String>, ParseExpParamError> {
+        let timestamp = match self.exp_timestamp() {
+            Ok(timestamp) => timestamp,
+            Err(ParseExpParamError::MissingExp) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let exp = chrono::DateTime::from_timestamp(timestamp.into(), 0)
+            .expect("u32 timestamps are always in range");
+        Ok(Some(exp.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)))
     }
 
     /// Set the exp parameter in the URL fragment
-    fn set_exp(&mut self, exp: std::time::SystemTime) {
+    ///
+    /// Times before the unix epoch are set as 0. The parameter holds a u32, so times after
+    /// 2106-02-07T06:28:15Z are an error rather than being clamped or truncated.
+    fn set_exp(&mut self, exp: std::time::SystemTime) -> Result<(), SetExpError> {
         let t = match exp.duration_since(std::time::UNIX_EPOCH) {
-            Ok(duration) => duration.as_secs().try_into().unwrap(), // TODO Result type instead of Option & unwrap
+            Ok(duration) =>
+                u32::try_from(duration.as_secs()).map_err(|_| SetExpError::Overflow(exp))?,
             Err(_) => 0u32,
         };
+        self.set_exp_timestamp(t);
+        Ok(())
+    }
+
+    /// Set the exp parameter in the URL fragment from seconds since the unix epoch
+    ///
+    /// Unlike [`UrlExt::set_exp`] this does not need a [`std::time::SystemTime`], which is not
+    /// available on every target, e.g. wasm32 in the browser. A fragment with invalid characters
+    /// or ambiguous delimiters is replaced.
+    fn set_exp_timestamp(&mut self, timestamp: u32) {
+        let exp_str = crate::bech32::nochecksum::encode(EX_HRP, &exp::encode(timestamp))
+            .expect("encoding u32 timestamp should never fail");
 
-        let mut buf = [0u8; 4];
-        t.consensus_encode(&mut &mut buf[..]).unwrap(); // TODO no unwrap
+        set_typed_param(self, EX_HRP, &exp_str);
+    }
 
-        let ex_hrp: Hrp = Hrp::parse("EX").unwrap();
+    /// Remove the exp parameter from the URL fragment
+    ///
+    /// A fragment with invalid characters or ambiguous delimiters has no exp parameter to read,
+    /// so it is left as is.
+    fn clear_exp(&mut self) {
+        match self.set_fragment_param(EX_HRP, None) {
+            Ok(()) | Err(SetFragmentParamError::InvalidFragment(_)) => {}
+            Err(e) => unreachable!("removing a param can only fail on the fragment: {e}"),
+        }
+    }
 
-        let exp_str = crate::bech32::nochecksum::encode(ex_hrp, &buf)
-            .expect("encoding u32 timestamp should never fail");
+    /// Check whether the exp parameter is before `now`
+    ///
+    /// An endpoint without an exp parameter never expires.
+    fn is_expired(&self, now: std::time::SystemTime) -> Result<bool, ParseExpParamError> {
+        match self.exp() {
+            Ok(exp) => Ok(now > exp),
+            Err(ParseExpParamError::MissingExp) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Check whether the exp parameter is before `now` in seconds since the unix epoch
+    ///
+    /// An endpoint without an exp parameter never expires.
+    fn is_expired_at_timestamp(&self, now: u32) -> Result<bool, ParseExpParamError> {
+        match self.exp_timestamp() {
+            Ok(exp) => Ok(now > exp),
+            Err(ParseExpParamError::MissingExp) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sort the fragment parameters and use the `-` delimiter
+    ///
+    /// Setting a parameter keeps the legacy `+` delimiter, so a fragment only switches to `-`
+    /// when this is called explicitly.
+    fn normalize_fragment(&mut self) -> Result<(), ParseFragmentError> { normalize_fragment(self) }
+
+    /// Sort the fragment parameters and use the `-` delimiter, like [`UrlExt::normalize_fragment`]
+    ///
+    /// Parameter values are left untouched, so endpoints which only differ in parameter order
+    /// or delimiter canonicalize to the same URL, e.g. to deduplicate stored endpoints.
+    fn canonicalize_fragment(&mut self) -> Result<(), ParseFragmentError> {
+        self.normalize_fragment()
+    }
 
-        set_param(self, &exp_str)
+    /// Retrieve the ohttp keys, receiver public key and optional expiry together
+    fn v2_params(&self) -> Result<V2Params, ParseV2ParamsError> {
+        let ohttp_keys = self.ohttp().map_err(ParseV2ParamsError::OhttpKeys)?;
+        let receiver_pubkey = self.receiver_pubkey().map_err(ParseV2ParamsError::ReceiverPubkey)?;
+        let exp = self.exp_opt().map_err(ParseV2ParamsError::Exp)?;
+        Ok(V2Params { ohttp_keys, receiver_pubkey, exp })
+    }
+
+    /// Build an endpoint carrying the ohttp keys, receiver public key and optional expiry
+    ///
+    /// This is the inverse of [`UrlExt::v2_params`]. Any exp already on `base` is removed when
+    /// `exp` is `None`.
+    fn with_v2_params(
+        base: Url,
+        ohttp_keys: OhttpKeys,
+        receiver_pubkey: HpkePublicKey,
+        exp: Option<std::time::SystemTime>,
+    ) -> Result<Url, SetExpError> {
+        let mut url = base;
+        url.set_ohttp(ohttp_keys);
+        url.set_receiver_pubkey(receiver_pubkey);
+        match exp {
+            Some(exp) => url.set_exp(exp)?,
+            None => url.clear_exp(),
+        }
+        Ok(url)
+    }
+
+    /// Retrieve every fragment parameter with its bech32 HRP, in the order they appear
+    fn fragment_params(&self) -> Result<Vec<(Hrp, &str)>, ParseFragmentError> {
+        let fragment = match self.fragment() {
+            Some(fragment) => fragment,
+            None => return Ok(vec![]),
+        };
+        let delim = check_fragment_delimiter(fragment)?;
+        fragment
+            .split(delim)
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let hrp = param_hrp(param)
+                    .ok_or_else(|| ParseFragmentError::InvalidHrp(param.to_owned()))?;
+                Ok((hrp, param))
+            })
+            .collect()
+    }
+
+    /// Add the fragment parameters of `other` whose HRP is not already present on this URL
+    ///
+    /// Parameters already present keep their value. Both fragments must be valid.
+    fn merge_fragment_from(&mut self, other: &Url) -> Result<(), ParseFragmentError> {
+        let present: BTreeSet<Hrp> =
+            self.fragment_params()?.into_iter().map(|(hrp, _)| hrp).collect();
+        for (hrp, param) in other.fragment_params()? {
+            if !present.contains(&hrp) {
+                self.set_fragment_param(hrp, Some(param))
+                    .expect("params of a valid fragment are uppercase and have their own hrp");
+            }
+        }
+        Ok(())
     }
 }
 
+/// The latest expiry that can be encoded in the exp parameter
+pub(crate) fn max_exp() -> std::time::SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs(u32::MAX as u64)
+}
+
+/// The longest fragment accepted in a payjoin endpoint, in bytes
+///
+/// The RK, OH and EX parameters take up well under 200 bytes, anything much longer is likely
+/// a malicious attempt to bloat the URI.
+pub const MAX_FRAGMENT_LENGTH: usize = 1024;
+
+/// Parse a payjoin endpoint URL, rejecting fragments which could not hold valid v2 parameters
 pub fn parse_with_fragment(endpoint: &str) -> Result<Url, BadEndpointError> {
     let url = Url::parse(endpoint).map_err(BadEndpointError::UrlParse)?;
 
     if let Some(fragment) = url.fragment() {
+        if fragment.len() > MAX_FRAGMENT_LENGTH {
+            return Err(BadEndpointError::FragmentTooLong(fragment.len()));
+        }
         if fragment.chars().any(|c| c.is_lowercase()) {
             return Err(BadEndpointError::LowercaseFragment);
         }
+        let delim = check_fragment_delimiter(fragment).map_err(|e| match e {
+            ParseFragmentError::AmbiguousDelimiter { .. } =>
+                BadEndpointError::AmbiguousFragmentDelimiter,
+            e => BadEndpointError::Fragment(e),
+        })?;
+        // Empty segments would be skipped when reading params but dropped when setting them, so
+        // the same endpoint could serialize two ways
+        if !fragment.is_empty() && fragment.split(delim).any(str::is_empty) {
+            return Err(BadEndpointError::EmptyFragmentSegment);
+        }
+        if let Some(hrp) = duplicate_param(fragment, delim) {
+            return Err(BadEndpointError::DuplicateFragmentParam(hrp));
+        }
     };
     Ok(url)
 }
 
-#[derive(Debug)]
-pub(crate) enum ParseFragmentError {
+/// Parse a payjoin endpoint URL like [`parse_with_fragment`], also requiring the fragment
+/// parameters to be sorted lexicographically
+///
+/// BIP 77 senders should reject unsorted fragments, but [`parse_with_fragment`] keeps accepting
+/// them for compatibility with receivers which don't sort their parameters yet.
+pub fn parse_with_fragment_strict(endpoint: &str) -> Result<Url, BadEndpointError> {
+    let url = parse_with_fragment(endpoint)?;
+    if let Some(fragment) = url.fragment() {
+        if let Ok(delim) = check_fragment_delimiter(fragment) {
+            // Compare HRPs rather than whole segments, e.g. `OH1..` must sort before `OH01..`.
+            // Malformed segments sort last, like in `normalize_fragment`.
+            let hrps: Vec<Option<Hrp>> = fragment.split(delim).map(param_hrp).collect();
+            let unsorted = hrps.windows(2).any(|pair| match (&pair[0], &pair[1]) {
+                (Some(a), Some(b)) => a >= b,
+                (None, Some(_)) => true,
+                (_, None) => false,
+            });
+            if unsorted {
+                return Err(BadEndpointError::FragmentNotSorted);
+            }
+        }
+    }
+    Ok(url)
+}
+
+/// Whether the endpoint looks like its fragment was percent-encoded twice
+///
+/// A `%2523` in the BIP21 URI decodes to a literal `%23` in the path instead of a `#`, which
+/// silently turns a v2 endpoint into one without v2 parameters.
+pub(crate) fn has_double_encoded_fragment(url: &Url) -> bool {
+    url.fragment().is_none() && url.path().to_ascii_uppercase().contains("%23")
+}
+
+/// The bech32 HRP of a fragment parameter
+///
+/// The bech32 data part never contains a `1`, so the last one is the separator.
+fn param_hrp(param: &str) -> Option<Hrp> {
+    param.rsplit_once('1').and_then(|(hrp, _)| Hrp::parse(hrp).ok())
+}
+
+/// The first HRP shared by more than one fragment parameter, if any
+///
+/// Only the first parameter with a given HRP would be read, so a second one could mislead about
+/// e.g. which receiver key is in use.
+fn duplicate_param(fragment: &str, delim: char) -> Option<Hrp> {
+    let mut seen = BTreeSet::new();
+    fragment.split(delim).filter_map(param_hrp).find(|hrp| !seen.insert(*hrp))
+}
+
+/// Error parsing the fragment of a payjoin endpoint
+#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseFragmentError {
+    /// A character other than an uppercase alphanumeric, `-` or `+`
     InvalidChar(char),
-    AmbiguousDelimiter,
+    /// A character outside the uppercase bech32 charset after a parameter's `1` separator
+    InvalidBech32Char(char),
+    /// Both `-` and `+` are used as parameter delimiters, first at these byte positions
+    AmbiguousDelimiter { plus_at: usize, dash_at: usize },
+    /// A parameter that does not start with a valid bech32 HRP
+    InvalidHrp(String),
 }
 
 impl std::error::Error for ParseFragmentError {
@@ -128,12 +430,51 @@ impl std::fmt::Display for ParseFragmentError {
 
         match &self {
             InvalidChar(c) => write!(f, "invalid character: {c} (must be uppercase)"),
-            AmbiguousDelimiter => write!(f, "ambiguous fragment delimiter (both + and - found)"),
+            InvalidBech32Char(c) =>
+                write!(f, "invalid character: {c} (not in the uppercase bech32 charset)"),
+            AmbiguousDelimiter { plus_at, dash_at } => write!(
+                f,
+                "ambiguous fragment delimiter (+ found at {plus_at} and - found at {dash_at})"
+            ),
+            InvalidHrp(param) => write!(f, "fragment parameter {param} has no valid bech32 hrp"),
         }
     }
 }
 
+/// Check that a payjoin endpoint fragment is well formed without parsing the whole URL
+///
+/// The fragment must be uppercase, use a single kind of parameter delimiter, and only contain
+/// bech32 characters in the data part of each parameter. The leading `#` is not included.
+pub fn validate_fragment(fragment: &str) -> Result<(), ParseFragmentError> {
+    check_fragment_delimiter(fragment).map(|_| ())
+}
+
+/// The fragment is not percent-decoded. BIP21 already decodes the `pj` value once, so a `%2D`
+/// delimiter in the URI reaches the endpoint as `-`. A `%2D` still left in the endpoint itself is
+/// rejected as an invalid character instead of being decoded here, which would make
+/// [`get_param`] and [`set_param`] disagree about the fragment they operate on.
 fn check_fragment_delimiter(fragment: &str) -> Result<char, ParseFragmentError> {
+    let delim = fragment_delimiter(fragment)?;
+
+    // The HRP may use any of the characters above, but the data part after the
+    // last `1` is restricted to the bech32 charset, which excludes `1`, `B`, `I`
+    // and `O`. Segments without a separator are not bech32 and left alone.
+    for param in fragment.split(delim) {
+        if let Some((_, data)) = param.rsplit_once('1') {
+            if let Some(c) = data.chars().find(|c| !BECH32_UPPERCASE_CHARSET.contains(*c)) {
+                return Err(ParseFragmentError::InvalidBech32Char(c));
+            }
+        }
+    }
+
+    Ok(delim)
+}
+
+/// The bech32 data charset, uppercased as fragment parameters must be
+const BECH32_UPPERCASE_CHARSET: &str = "QPZRY9X8GF2TVDW0S3JN54KHCE6MUA7L";
+
+/// Determine the fragment delimiter, checking only for uppercase alphanumerics and delimiters
+fn fragment_delimiter(fragment: &str) -> Result<char, ParseFragmentError> {
     // For backwards compatibility, also accept `+` as a
     // fragment parameter delimiter. This was previously
     // specified, but may be interpreted as ` ` by some
@@ -141,8 +482,8 @@ fn check_fragment_delimiter(fragment: &str) -> Result<char, ParseFragmentError>
     // assume the URI was generated following the older
     // version of the spec.
 
-    let has_dash = fragment.contains('-');
-    let has_plus = fragment.contains('+');
+    let dash_at = fragment.find('-');
+    let plus_at = fragment.find('+');
 
     // Even though fragment is a &str, it should be ascii so bytes() correspond
     // to chars(), except that it's easier to check that they are in range
@@ -159,14 +500,35 @@ fn check_fragment_delimiter(fragment: &str) -> Result<char, ParseFragmentError>
         }
     }
 
-    match (has_dash, has_plus) {
-        (true, true) => Err(ParseFragmentError::AmbiguousDelimiter),
-        (false, true) => Ok('+'),
+    match (dash_at, plus_at) {
+        (Some(dash_at), Some(plus_at)) =>
+            Err(ParseFragmentError::AmbiguousDelimiter { plus_at, dash_at }),
+        (None, Some(_)) => Ok('+'),
         _ => Ok('-'),
     }
 }
 
-fn get_param<'a>(url: &'a Url, prefix: &str) -> Result<Option<&'a str>, ParseFragmentError> {
+/// Decode a bech32 encoded receiver public key parameter, including its HRP
+fn decode_receiver_pubkey(value: &str) -> Result<HpkePublicKey, ParseReceiverPubkeyParamError> {
+    let (hrp, bytes) = crate::bech32::nochecksum::decode(value)
+        .map_err(ParseReceiverPubkeyParamError::DecodeBech32)?;
+
+    if hrp != RK_HRP {
+        return Err(ParseReceiverPubkeyParamError::InvalidHrp {
+            expected: Box::new(RK_HRP),
+            actual: Box::new(hrp),
+        });
+    }
+
+    if bytes.len() != PUBLIC_KEY_SIZE {
+        return Err(ParseReceiverPubkeyParamError::InvalidPubkeyLength(bytes.len()));
+    }
+
+    HpkePublicKey::from_compressed_bytes(&bytes[..])
+        .map_err(ParseReceiverPubkeyParamError::InvalidPubkey)
+}
+
+fn get_param(url: &Url, hrp: Hrp) -> Result<Option<&str>, ParseFragmentError> {
     if let Some(fragment) = url.fragment() {
         let delim = check_fragment_delimiter(fragment)?;
 
@@ -175,8 +537,10 @@ fn get_param<'a>(url: &'a Url, prefix: &str) -> Result<Option<&'a str>, ParseFra
         // for privacy reasons (fingerprinting implementations).
         // To maintain compatibility, we don't care about the order
         // of the parameters.
+        //
+        // Match the whole HRP, e.g. `RK1X1..` has the HRP `RK1X` and must not be read as `RK`.
         for param in fragment.split(delim) {
-            if param.starts_with(prefix) {
+            if param_hrp(param) == Some(hrp) {
                 return Ok(Some(param));
             }
         }
@@ -185,42 +549,133 @@ fn get_param<'a>(url: &'a Url, prefix: &str) -> Result<Option<&'a str>, ParseFra
 }
 
 /// Set a URL fragment parameter, inserting it or replacing it depending on
-/// whether a parameter with the same bech32 HRP is already present.
+/// whether a parameter with the same bech32 HRP is already present, or
+/// removing it if `new_param` is `None`.
+///
+/// Parameters are sorted lexicographically by HRP. The delimiter already in
+/// use by the fragment is kept, so a legacy `+` delimited fragment stays `+`
+/// delimited. Only [`UrlExt::normalize_fragment`] rewrites it to `-`. A
+/// fragment which had no delimiter, i.e. at most one parameter, uses `-`.
+///
+/// A parameter containing lowercase characters, a delimiter or data outside
+/// the bech32 charset is rejected, since the resulting fragment would not
+/// parse. Existing segments without a valid HRP,
+/// e.g. without a `1` separator, are kept in their original order after the
+/// sorted parameters.
 ///
-/// Parameters are sorted lexicographically by prefix.
-fn set_param(url: &mut Url, new_param: &str) {
+/// A fragment left without parameters is removed along with its `#`, so a
+/// bare `#` is cleared by removing any parameter and replaced when setting one.
+///
+/// A fragment with characters other than uppercase alphanumerics and
+/// delimiters, or with both delimiters, is an error rather than being
+/// rewritten, since its segments could not be told apart.
+fn set_param(
+    url: &mut Url,
+    key: Hrp,
+    new_param: Option<&str>,
+) -> Result<(), SetFragmentParamError> {
+    if let Some(param) = new_param {
+        if param.chars().any(|c| c.is_lowercase()) {
+            return Err(SetFragmentParamError::LowercaseParam);
+        }
+        // The param must stay a single segment of the fragment
+        if let Some(c) = param.chars().find(|c| matches!(c, '-' | '+')) {
+            return Err(SetFragmentParamError::InvalidParam(ParseFragmentError::InvalidChar(c)));
+        }
+        check_fragment_delimiter(param).map_err(SetFragmentParamError::InvalidParam)?;
+    }
+
     let fragment = url.fragment().unwrap_or("");
-    let delim = check_fragment_delimiter(fragment)
-        .expect("set_param must be called on a URL with a valid fragment");
+    let delim = fragment_delimiter(fragment).map_err(SetFragmentParamError::InvalidFragment)?;
 
     // In case of an invalid fragment parameter the following will still attempt
     // to retain the existing data
-    let mut params = fragment
+    let mut params = split_params(fragment, delim);
+
+    match new_param {
+        Some(param) => params.insert(ParamKey::Hrp(key), param),
+        None => params.remove(&ParamKey::Hrp(key)),
+    };
+
+    let fragment = join_params(&params, delim);
+    url.set_fragment(fragment.as_deref());
+    Ok(())
+}
+
+/// Set a parameter the typed setters encode themselves, replacing the fragment if it is invalid
+///
+/// The fragment could not hold the v2 parameters anyway, so the typed setters stay infallible.
+fn set_typed_param(url: &mut Url, hrp: Hrp, param: &str) {
+    let result = match set_param(url, hrp, Some(param)) {
+        Err(SetFragmentParamError::InvalidFragment(_)) => {
+            url.set_fragment(None);
+            set_param(url, hrp, Some(param))
+        }
+        result => result,
+    };
+    result.expect("typed params are valid uppercase bech32 segments")
+}
+
+/// Sort the fragment parameters lexicographically and join them with `-`
+fn normalize_fragment(url: &mut Url) -> Result<(), ParseFragmentError> {
+    let fragment = url.fragment().unwrap_or("");
+    let delim = check_fragment_delimiter(fragment)?;
+    let fragment = join_params(&split_params(fragment, delim), '-');
+    url.set_fragment(fragment.as_deref());
+    Ok(())
+}
+
+/// The key of a fragment parameter in [`split_params`]
+///
+/// Malformed segments sort after all parameters with an HRP, by their position in the fragment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ParamKey {
+    Hrp(Hrp),
+    Malformed(usize),
+}
+
+/// Map the fragment parameters by their bech32 HRP
+///
+/// Segments without a valid HRP, e.g. without a `1` separator or with nothing before it, are kept
+/// under a unique [`ParamKey::Malformed`] key so they can neither collide with each other nor
+/// with a valid parameter.
+fn split_params(fragment: &str, delim: char) -> BTreeMap<ParamKey, &str> {
+    fragment
         .split(delim)
         .filter(|param| !param.is_empty())
-        .map(|param| {
-            let key = param.split('1').next().unwrap_or(param);
+        .enumerate()
+        .map(|(i, param)| {
+            let key = match param_hrp(param) {
+                Some(hrp) => ParamKey::Hrp(hrp),
+                None => ParamKey::Malformed(i),
+            };
             (key, param)
         })
-        .collect::<BTreeMap<&str, &str>>();
-
-    // TODO: change param to Option(&str) to allow deletion?
-    let key = new_param.split('1').next().unwrap_or(new_param);
-    params.insert(key, new_param);
+        .collect()
+}
 
-    if params.is_empty() {
-        url.set_fragment(None)
-    } else {
-        // Can we avoid intermediate allocation of Vec, intersperse() exists but not in MSRV
-        let fragment = params.values().copied().collect::<Vec<_>>().join("-");
-        url.set_fragment(Some(&fragment));
+/// Join parameters into a fragment, or `None` if there are no parameters
+fn join_params(params: &BTreeMap<ParamKey, &str>, delim: char) -> Option<String> {
+    let mut values = params.values();
+    let first = values.next()?;
+    let len = params.values().map(|param| param.len()).sum::<usize>() + params.len() - 1;
+    let mut fragment = String::with_capacity(len);
+    fragment.push_str(first);
+    for param in values {
+        fragment.push(delim);
+        fragment.push_str(param);
     }
+    Some(fragment)
 }
 
+/// Error reading the ohttp parameter of a payjoin endpoint
 #[derive(Debug)]
-pub(crate) enum ParseOhttpKeysParamError {
+pub enum ParseOhttpKeysParamError {
+    /// The fragment has no ohttp parameter
     MissingOhttpKeys,
+    /// The ohttp parameter does not hold valid keys
     InvalidOhttpKeys(crate::ohttp::ParseOhttpKeysError),
+    /// The fragment itself is malformed
     InvalidFragment(ParseFragmentError),
 }
 
@@ -236,12 +691,30 @@ impl std::fmt::Display for ParseOhttpKeysParamError {
     }
 }
 
+impl std::error::Error for ParseOhttpKeysParamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ParseOhttpKeysParamError::*;
+
+        match &self {
+            MissingOhttpKeys => None,
+            InvalidOhttpKeys(error) => Some(error),
+            InvalidFragment(error) => Some(error),
+        }
+    }
+}
+
+/// Error reading the exp parameter of a payjoin endpoint
 #[derive(Debug)]
-pub(crate) enum ParseExpParamError {
+pub enum ParseExpParamError {
+    /// The fragment has no exp parameter
     MissingExp,
-    InvalidHrp(bitcoin::bech32::Hrp),
+    /// The exp parameter has another HRP
+    InvalidHrp { expected: Box<bitcoin::bech32::Hrp>, actual: Box<bitcoin::bech32::Hrp> },
+    /// The exp parameter is not valid bech32
     DecodeBech32(bitcoin::bech32::primitives::decode::CheckedHrpstringError),
-    InvalidExp(bitcoin::consensus::encode::Error),
+    /// The payload has this length instead of the 4 bytes of a u32 timestamp
+    InvalidExp(usize),
+    /// The fragment itself is malformed
     InvalidFragment(ParseFragmentError),
 }
 
@@ -251,21 +724,134 @@ impl std::fmt::Display for ParseExpParamError {
 
         match &self {
             MissingExp => write!(f, "exp is missing"),
-            InvalidHrp(h) => write!(f, "incorrect hrp for exp: {h}"),
+            InvalidHrp { expected, actual } =>
+                write!(f, "incorrect hrp for exp: expected {expected}, got {actual}"),
             DecodeBech32(d) => write!(f, "exp is not valid bech32: {d}"),
-            InvalidExp(i) =>
-                write!(f, "exp param does not contain a bitcoin consensus encoded u32: {i}"),
+            InvalidExp(len) =>
+                write!(f, "exp param is {len} bytes instead of a {} byte timestamp", exp::EXP_BYTES),
             InvalidFragment(e) => write!(f, "invalid URL fragment: {e}"),
         }
     }
 }
 
+impl std::error::Error for ParseExpParamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ParseExpParamError::*;
+
+        match &self {
+            MissingExp => None,
+            InvalidHrp { .. } => None,
+            DecodeBech32(error) => Some(error),
+            InvalidExp(_) => None,
+            InvalidFragment(error) => Some(error),
+        }
+    }
+}
+
+/// The expiry could not be set on a payjoin endpoint
+#[derive(Debug)]
+pub enum SetExpError {
+    /// The time is later than 2106-02-07T06:28:15Z, the last one a u32 timestamp can encode
+    Overflow(std::time::SystemTime),
+}
+
+impl std::fmt::Display for SetExpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use SetExpError::*;
+
+        match &self {
+            Overflow(t) =>
+                write!(f, "exp {t:?} is too far in the future to encode as a u32 timestamp"),
+        }
+    }
+}
+
+impl std::error::Error for SetExpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+/// A fragment parameter could not be set on a payjoin endpoint
+#[derive(Debug, PartialEq, Eq)]
+pub enum SetFragmentParamError {
+    /// The param contains lowercase characters, which the fragment must not have
+    LowercaseParam,
+    /// The param does not have the HRP it was set as
+    HrpMismatch(Hrp),
+    /// The param is not a single well formed fragment segment, e.g. it contains a delimiter
+    InvalidParam(ParseFragmentError),
+    /// The existing fragment has invalid characters or ambiguous delimiters
+    InvalidFragment(ParseFragmentError),
+}
+
+impl std::fmt::Display for SetFragmentParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use SetFragmentParamError::*;
+
+        match &self {
+            LowercaseParam => write!(f, "fragment params must not contain lowercase characters"),
+            HrpMismatch(hrp) => write!(f, "fragment param set as {hrp} has a different hrp"),
+            InvalidParam(e) => write!(f, "invalid fragment param: {e}"),
+            InvalidFragment(e) => write!(f, "invalid fragment: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SetFragmentParamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self {
+            SetFragmentParamError::InvalidParam(e) | SetFragmentParamError::InvalidFragment(e) =>
+                Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Error reading the [`V2Params`] of a payjoin endpoint
 #[derive(Debug)]
-pub(crate) enum ParseReceiverPubkeyParamError {
+pub enum ParseV2ParamsError {
+    OhttpKeys(ParseOhttpKeysParamError),
+    ReceiverPubkey(ParseReceiverPubkeyParamError),
+    Exp(ParseExpParamError),
+}
+
+impl std::fmt::Display for ParseV2ParamsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ParseV2ParamsError::*;
+
+        match &self {
+            OhttpKeys(e) => write!(f, "{e}"),
+            ReceiverPubkey(e) => write!(f, "{e}"),
+            Exp(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseV2ParamsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ParseV2ParamsError::*;
+
+        match &self {
+            OhttpKeys(error) => Some(error),
+            ReceiverPubkey(error) => Some(error),
+            Exp(error) => Some(error),
+        }
+    }
+}
+
+/// Error reading the receiver public key parameter of a payjoin endpoint
+#[derive(Debug)]
+pub enum ParseReceiverPubkeyParamError {
+    /// The fragment has no receiver public key parameter
     MissingPubkey,
-    InvalidHrp(bitcoin::bech32::Hrp),
+    /// The receiver public key parameter has another HRP
+    InvalidHrp { expected: Box<bitcoin::bech32::Hrp>, actual: Box<bitcoin::bech32::Hrp> },
+    /// The receiver public key parameter is not valid bech32
     DecodeBech32(bitcoin::bech32::primitives::decode::CheckedHrpstringError),
+    /// The payload has this length instead of that of a compressed public key
+    InvalidPubkeyLength(usize),
+    /// The payload is not a valid public key
     InvalidPubkey(crate::hpke::HpkeError),
+    /// The fragment itself is malformed
     InvalidFragment(ParseFragmentError),
 }
 
@@ -275,8 +861,13 @@ impl std::fmt::Display for ParseReceiverPubkeyParamError {
 
         match &self {
             MissingPubkey => write!(f, "receiver public key is missing"),
-            InvalidHrp(h) => write!(f, "incorrect hrp for receiver key: {h}"),
-            DecodeBech32(e) => write!(f, "receiver public is not valid base64: {e}"),
+            InvalidHrp { expected, actual } =>
+                write!(f, "incorrect hrp for receiver key: expected {expected}, got {actual}"),
+            DecodeBech32(e) => write!(f, "receiver public key is not valid bech32: {e}"),
+            InvalidPubkeyLength(len) => write!(
+                f,
+                "receiver public key is {len} bytes but a compressed pubkey is {PUBLIC_KEY_SIZE}"
+            ),
             InvalidPubkey(e) =>
                 write!(f, "receiver public key does not represent a valid pubkey: {e}"),
             InvalidFragment(e) => write!(f, "invalid URL fragment: {e}"),
@@ -290,8 +881,9 @@ impl std::error::Error for ParseReceiverPubkeyParamError {
 
         match &self {
             MissingPubkey => None,
-            InvalidHrp(_) => None,
+            InvalidHrp { .. } => None,
             DecodeBech32(error) => Some(error),
+            InvalidPubkeyLength(_) => None,
             InvalidPubkey(error) => Some(error),
             InvalidFragment(error) => Some(error),
         }
@@ -305,6 +897,79 @@ mod tests {
     use super::*;
     use crate::{Uri, UriExt};
 
+    #[test]
+    fn test_fragment_params() {
+        let url = Url::parse(
+            "https://example.com/#OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC\
+             -EX1C4UC6ES-RK1Q0DJS3VVDXWQQTLQ8022QGXSX7ML9PHZ6EDSF6AKEWQG758JPS2EVGFA8W",
+        )
+        .unwrap();
+        let params = url.fragment_params().unwrap();
+        let hrps: Vec<_> = params.iter().map(|(hrp, _)| hrp.as_str()).collect();
+        assert_eq!(hrps, ["OH", "EX", "RK"]);
+        assert_eq!(params[1].1, "EX1C4UC6ES");
+
+        assert!(EXAMPLE_URL.fragment_params().unwrap().is_empty());
+        assert!(matches!(
+            Url::parse("https://example.com/#EX1C4UC6ES-NOHRP").unwrap().fragment_params(),
+            Err(ParseFragmentError::InvalidHrp(param)) if param == "NOHRP"
+        ));
+    }
+
+    #[test]
+    fn test_v2_params() {
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        let mut url = Url::parse(&format!("https://example.com/#{ohttp}-EX1C4UC6ES")).unwrap();
+        let receiver_pubkey = crate::HpkeKeyPair::gen_keypair().public_key().clone();
+        url.set_receiver_pubkey(receiver_pubkey.clone());
+
+        let params = url.v2_params().unwrap();
+        assert_eq!(params.ohttp_keys, OhttpKeys::from_str(ohttp).unwrap());
+        assert_eq!(params.receiver_pubkey, receiver_pubkey);
+        assert_eq!(params.exp, Some(url.exp().unwrap()));
+
+        url.clear_exp();
+        assert_eq!(url.v2_params().unwrap().exp, None);
+    }
+
+    #[test]
+    fn test_with_v2_params() {
+        let ohttp = "OH1QYP87E2AVMDKXDTU6R25WCPQ5ZUF02XHNPA65JMD8ZA2W4YRQN6UUWG";
+        let rk = "RK1QG2RH36X9ZWRK7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG";
+        let ex = "EX1XPK8Y6Q";
+        let vector = Url::parse(&format!("https://example.com/#{ex}-{ohttp}-{rk}")).unwrap();
+        let params = vector.v2_params().unwrap();
+
+        let url = Url::with_v2_params(
+            Url::parse("https://example.com").unwrap(),
+            params.ohttp_keys.clone(),
+            params.receiver_pubkey.clone(),
+            params.exp,
+        )
+        .unwrap();
+        assert_eq!(url.fragment(), Some(&format!("{ex}-{ohttp}-{rk}")[..]));
+        let reparsed = parse_with_fragment(url.as_str()).unwrap().v2_params().unwrap();
+        assert_eq!(reparsed.ohttp_keys, params.ohttp_keys);
+        assert_eq!(reparsed.receiver_pubkey, params.receiver_pubkey);
+        assert_eq!(reparsed.exp, params.exp);
+
+        let url =
+            Url::with_v2_params(vector, params.ohttp_keys, params.receiver_pubkey, None).unwrap();
+        assert_eq!(url.fragment(), Some(&format!("{ohttp}-{rk}")[..]));
+    }
+
+    #[test]
+    fn test_v2_params_missing_receiver_pubkey() {
+        let url = Url::parse(
+            "https://example.com/#OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC-EX1C4UC6ES",
+        )
+        .unwrap();
+        assert!(matches!(
+            url.v2_params(),
+            Err(ParseV2ParamsError::ReceiverPubkey(ParseReceiverPubkeyParamError::MissingPubkey))
+        ));
+    }
+
     #[test]
     fn test_ohttp_get_set() {
         let mut url = EXAMPLE_URL.clone();
@@ -335,6 +1000,326 @@ mod tests {
             invalid_ohttp_url.ohttp(),
             Err(ParseOhttpKeysParamError::InvalidFragment(_))
         ));
+
+        let truncated_ohttp_url = Url::parse("https://example.com#OH1QYPM5JXYNS754").unwrap();
+        assert!(matches!(
+            truncated_ohttp_url.ohttp(),
+            Err(ParseOhttpKeysParamError::InvalidOhttpKeys(
+                crate::ohttp::ParseOhttpKeysError::Truncated
+            ))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "chrono")]
+    fn test_exp_rfc3339() {
+        let url = Url::parse("http://example.com#EX1C4UC6ES").unwrap();
+        assert_eq!(url.exp_rfc3339().unwrap(), Some("2024-07-09T17:56:21Z".to_string()));
+
+        assert_eq!(EXAMPLE_URL.exp_rfc3339().unwrap(), None);
+
+        let invalid_exp = Url::parse("http://example.com#EX1INVALIDBECH32").unwrap();
+        assert!(invalid_exp.exp_rfc3339().is_err());
+    }
+
+    #[test]
+    fn test_hrp_constants() {
+        assert_eq!(RK_HRP, Hrp::parse("RK").unwrap());
+        assert_eq!(OH_HRP, Hrp::parse("OH").unwrap());
+        assert_eq!(EX_HRP, Hrp::parse("EX").unwrap());
+    }
+
+    #[test]
+    fn test_unknown_fragment_param_get_set() {
+        let mut url = EXAMPLE_URL.clone();
+        let zz_hrp = Hrp::parse("ZZ").unwrap();
+        let zz_param = "ZZ1QQQSYQCYQ5RQWZQF";
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+
+        url.set_fragment_param(zz_hrp, Some(zz_param)).unwrap();
+        url.set_ohttp(OhttpKeys::from_str(ohttp).unwrap());
+        assert_eq!(url.fragment(), Some(&format!("{ohttp}-{zz_param}")[..]));
+        assert_eq!(url.fragment_param(zz_hrp).unwrap(), Some(zz_param));
+        assert!(url.ohttp().is_ok());
+
+        assert_eq!(url.fragment_param(EX_HRP).unwrap(), None);
+        // Downstream crates reach the accessors through the crate root
+        assert_eq!(crate::UrlExt::fragment_param(&url, zz_hrp).unwrap(), Some(zz_param));
+
+        url.set_fragment_param(zz_hrp, None).unwrap();
+        assert_eq!(url.fragment(), Some(ohttp));
+        assert_eq!(url.fragment_param(zz_hrp).unwrap(), None);
+    }
+
+    #[test]
+    fn test_fragment_param_with_separator_in_hrp() {
+        // The HRP of `ZZ1AB1CD` is `ZZ1AB`, so it is a different parameter than `ZZ1QQ`
+        let mut url = Url::parse("https://example.com/#ZZ1AB1CD").unwrap();
+        url.set_fragment_param(Hrp::parse("ZZ").unwrap(), Some("ZZ1QQ")).unwrap();
+        assert_eq!(url.fragment(), Some("ZZ1QQ-ZZ1AB1CD"));
+        assert!(parse_with_fragment_strict(url.as_str()).is_ok());
+
+        let mut url = Url::parse("https://example.com/#ZZ1AB1CD+ZZ1QQ").unwrap();
+        url.normalize_fragment().unwrap();
+        assert_eq!(url.fragment(), Some("ZZ1QQ-ZZ1AB1CD"));
+        assert!(parse_with_fragment_strict(url.as_str()).is_ok());
+    }
+
+    #[test]
+    fn test_get_param_matches_whole_hrp() {
+        let url = Url::parse("https://example.com/#ZZ1AB1CD").unwrap();
+        assert_eq!(url.fragment_param(Hrp::parse("ZZ").unwrap()).unwrap(), None);
+        assert_eq!(url.fragment_param(Hrp::parse("ZZ1AB").unwrap()).unwrap(), Some("ZZ1AB1CD"));
+
+        let receiver_pubkey = crate::HpkeKeyPair::gen_keypair().public_key().clone();
+        let mut url = EXAMPLE_URL.clone();
+        url.set_receiver_pubkey(receiver_pubkey.clone());
+        let rk = url.fragment().unwrap().to_owned();
+        url.set_fragment(Some(&format!("RK1X1QQ-{rk}")));
+        assert_eq!(url.fragment_param(RK_HRP).unwrap(), Some(rk.as_str()));
+        assert_eq!(url.receiver_pubkey().unwrap(), receiver_pubkey);
+    }
+
+    #[test]
+    fn test_set_param_on_invalid_fragment() {
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        for fragment in ["abc", "EX1C4UC6ES-OH1Q+RK1Q"] {
+            let mut url = Url::parse(&format!("https://example.com/#{fragment}")).unwrap();
+            assert!(matches!(
+                url.set_fragment_param(Hrp::parse("ZZ").unwrap(), Some("ZZ1QQ")),
+                Err(SetFragmentParamError::InvalidFragment(_))
+            ));
+            url.clear_exp();
+            assert_eq!(url.fragment(), Some(fragment));
+
+            url.set_exp(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1720547781))
+                .unwrap();
+            assert_eq!(url.fragment(), Some("EX1C4UC6ES"));
+
+            let mut url = Url::parse(&format!("https://example.com/#{fragment}")).unwrap();
+            url.set_ohttp(OhttpKeys::from_str(ohttp).unwrap());
+            assert_eq!(url.fragment(), Some(ohttp));
+        }
+    }
+
+    #[test]
+    fn test_fragment_param_without_valid_hrp() {
+        let mut url = Url::parse("https://example.com/#1QQ-JUNK-1PP").unwrap();
+        url.set_fragment_param(Hrp::parse("ZZ").unwrap(), Some("ZZ1QQ")).unwrap();
+        assert_eq!(url.fragment(), Some("ZZ1QQ-1QQ-JUNK-1PP"));
+
+        url.set_fragment_param(Hrp::parse("ZZ").unwrap(), None).unwrap();
+        assert_eq!(url.fragment(), Some("1QQ-JUNK-1PP"));
+
+        let mut url = Url::parse("https://example.com/#1QQ+1PP+EX1C4UC6ES").unwrap();
+        url.normalize_fragment().unwrap();
+        assert_eq!(url.fragment(), Some("EX1C4UC6ES-1QQ-1PP"));
+    }
+
+    #[test]
+    fn test_merge_fragment_from() {
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        let mut url = Url::parse(&format!("https://example.com/#{ohttp}")).unwrap();
+        let other = Url::parse("https://other.example/#EX1C4UC6ES").unwrap();
+        url.merge_fragment_from(&other).unwrap();
+        assert_eq!(url.fragment(), Some(&format!("EX1C4UC6ES-{ohttp}")[..]));
+        assert_eq!(url.host_str(), Some("example.com"));
+
+        let other = Url::parse("https://other.example/#EX1XPK8Y6Q-ZZ1QQQSYQCYQ5RQWZQF").unwrap();
+        url.merge_fragment_from(&other).unwrap();
+        assert_eq!(url.fragment(), Some(&format!("EX1C4UC6ES-{ohttp}-ZZ1QQQSYQCYQ5RQWZQF")[..]));
+
+        url.merge_fragment_from(&EXAMPLE_URL).unwrap();
+        assert_eq!(url.fragment(), Some(&format!("EX1C4UC6ES-{ohttp}-ZZ1QQQSYQCYQ5RQWZQF")[..]));
+
+        let invalid = Url::parse("https://other.example/#ex1c4uc6es").unwrap();
+        assert!(matches!(
+            url.merge_fragment_from(&invalid),
+            Err(ParseFragmentError::InvalidChar('e'))
+        ));
+    }
+
+    #[test]
+    fn test_set_param_with_malformed_segments() {
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        let mut url = EXAMPLE_URL.clone();
+        url.set_fragment(Some(&format!("JUNK-OH-{ohttp}-JUNK")));
+
+        url.set_exp(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1720547781)).unwrap();
+        assert_eq!(url.fragment(), Some(&format!("EX1C4UC6ES-{ohttp}-JUNK-OH-JUNK")[..]));
+        assert!(url.ohttp().is_ok());
+        assert!(url.exp().is_ok());
+
+        url.clear_exp();
+        assert_eq!(url.fragment(), Some(&format!("{ohttp}-JUNK-OH-JUNK")[..]));
+    }
+
+    #[test]
+    fn test_set_param_on_empty_fragment() {
+        let mut url = Url::parse("https://example.com/#").unwrap();
+        assert_eq!(url.fragment(), Some(""));
+        url.clear_exp();
+        assert_eq!(url.fragment(), None);
+        assert_eq!(url.as_str(), "https://example.com/");
+
+        let mut url = Url::parse("https://example.com/#").unwrap();
+        url.set_exp(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1720547781)).unwrap();
+        assert_eq!(url.as_str(), "https://example.com/#EX1C4UC6ES");
+        url.clear_exp();
+        assert_eq!(url.as_str(), "https://example.com/");
+
+        let mut url = Url::parse("https://example.com/").unwrap();
+        url.clear_exp();
+        assert_eq!(url.as_str(), "https://example.com/");
+    }
+
+    #[test]
+    fn test_has_double_encoded_fragment() {
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        let double_encoded =
+            Url::parse(&format!("https://example.com/%23EX1C4UC6ES-{ohttp}")).unwrap();
+        assert!(has_double_encoded_fragment(&double_encoded));
+        assert!(double_encoded.ohttp().is_err());
+
+        let lowercase = Url::parse("https://example.com/pj%23").unwrap();
+        assert!(has_double_encoded_fragment(&lowercase));
+
+        let encoded_once = Url::parse(&format!("https://example.com/#EX1C4UC6ES-{ohttp}")).unwrap();
+        assert!(!has_double_encoded_fragment(&encoded_once));
+        assert!(!has_double_encoded_fragment(&EXAMPLE_URL));
+    }
+
+    #[test]
+    fn test_join_params() {
+        fn join_with_vec(params: &BTreeMap<ParamKey, &str>, delim: char) -> Option<String> {
+            if params.is_empty() {
+                None
+            } else {
+                Some(params.values().copied().collect::<Vec<_>>().join(&delim.to_string()))
+            }
+        }
+
+        for fragment in [
+            "",
+            "OH1QYPM5JXYNS754",
+            "EX1C4UC6ES-OH1QYPM5JXYNS754-RK1Q",
+            "RK1Q+EX1C4UC6ES+OH1QYPM5JXYNS754",
+            "JUNK-OH-OH1QYPM5JXYNS754-JUNK",
+        ] {
+            for delim in ['-', '+'] {
+                let params = split_params(fragment, delim);
+                assert_eq!(join_params(&params, delim), join_with_vec(&params, delim));
+            }
+        }
+    }
+
+    #[test]
+    fn test_percent_encoded_delimiter_in_url() {
+        let url = Url::parse("https://example.com/#EX1C4UC6ES%2DOH1QYPM5JXYNS754").unwrap();
+        assert_eq!(url.fragment(), Some("EX1C4UC6ES%2DOH1QYPM5JXYNS754"));
+        assert!(matches!(url.fragment_params(), Err(ParseFragmentError::InvalidChar('%'))));
+        assert!(matches!(
+            url.exp(),
+            Err(ParseExpParamError::InvalidFragment(ParseFragmentError::InvalidChar('%')))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_bech32_char_in_fragment() {
+        for c in ['B', 'I', 'O'] {
+            let url =
+                Url::parse(&format!("https://example.com/#EX1C4U{c}6ES-OH1QYPM5JXYNS754")).unwrap();
+            assert!(matches!(
+                url.exp(),
+                Err(ParseExpParamError::InvalidFragment(ParseFragmentError::InvalidBech32Char(e)))
+                    if e == c
+            ));
+            assert!(matches!(
+                url.fragment_params(),
+                Err(ParseFragmentError::InvalidBech32Char(e)) if e == c
+            ));
+        }
+
+        // The characters are allowed in the HRP and in segments which are not bech32
+        let url = Url::parse("https://example.com/#OH-BIO-EX1C4UC6ES").unwrap();
+        assert!(url.exp().is_ok());
+
+        // Setting a param keeps an existing invalid one rather than panicking
+        let mut url = Url::parse("https://example.com/#EX1C4UB6ES").unwrap();
+        url.set_ohttp(
+            OhttpKeys::from_str("OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
+                .unwrap(),
+        );
+        assert!(url.fragment().unwrap().starts_with("EX1C4UB6ES-OH1"));
+    }
+
+    #[test]
+    fn test_rotate_receiver_pubkey() {
+        let mut url = EXAMPLE_URL.clone();
+        let old = crate::HpkeKeyPair::gen_keypair().public_key().clone();
+        let new = crate::HpkeKeyPair::gen_keypair().public_key().clone();
+
+        assert_eq!(url.rotate_receiver_pubkey(old.clone()), None);
+        assert_eq!(url.receiver_pubkey().unwrap(), old);
+
+        assert_eq!(url.rotate_receiver_pubkey(new.clone()), Some(old));
+        assert_eq!(url.receiver_pubkey().unwrap(), new);
+    }
+
+    #[test]
+    fn test_set_lowercase_fragment_param() {
+        let mut url = EXAMPLE_URL.clone();
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        url.set_ohttp(OhttpKeys::from_str(ohttp).unwrap());
+
+        let zz_hrp = Hrp::parse("ZZ").unwrap();
+        assert_eq!(
+            url.set_fragment_param(zz_hrp, Some("zz1qqqsyqcyq5rqwzqf")),
+            Err(SetFragmentParamError::LowercaseParam)
+        );
+        assert_eq!(url.fragment(), Some(ohttp));
+    }
+
+    #[test]
+    fn test_set_fragment_param_hrp_mismatch() {
+        let mut url = EXAMPLE_URL.clone();
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        url.set_ohttp(OhttpKeys::from_str(ohttp).unwrap());
+
+        for param in ["EX1C4UC6ES", "OHX1C4UC6ES", "NOSEPARATOR"] {
+            assert_eq!(
+                url.set_fragment_param(OH_HRP, Some(param)),
+                Err(SetFragmentParamError::HrpMismatch(OH_HRP)),
+                "{param}"
+            );
+        }
+        assert_eq!(url.fragment(), Some(ohttp));
+        assert!(url.ohttp().is_ok());
+    }
+
+    #[test]
+    fn test_set_invalid_fragment_param() {
+        let mut url = EXAMPLE_URL.clone();
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        url.set_ohttp(OhttpKeys::from_str(ohttp).unwrap());
+
+        let zz_hrp = Hrp::parse("ZZ").unwrap();
+        for (param, c) in [("ZZ1QQ+", '+'), ("ZZ1QQ-", '-')] {
+            assert_eq!(
+                url.set_fragment_param(zz_hrp, Some(param)),
+                Err(SetFragmentParamError::InvalidParam(ParseFragmentError::InvalidChar(c)))
+            );
+        }
+        assert_eq!(
+            url.set_fragment_param(zz_hrp, Some("ZZ1B")),
+            Err(SetFragmentParamError::InvalidParam(ParseFragmentError::InvalidBech32Char('B')))
+        );
+        assert_eq!(
+            url.set_fragment_param(zz_hrp, Some("ZZ1Q_Q")),
+            Err(SetFragmentParamError::InvalidParam(ParseFragmentError::InvalidChar('_')))
+        );
+        assert_eq!(url.fragment(), Some(ohttp));
+        assert!(parse_with_fragment(url.as_str()).is_ok());
     }
 
     #[test]
@@ -343,16 +1328,136 @@ mod tests {
 
         let exp_time =
             std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1720547781);
-        url.set_exp(exp_time);
+        url.set_exp(exp_time).unwrap();
         assert_eq!(url.fragment(), Some("EX1C4UC6ES"));
+        assert_eq!(
+            url.exp_timestamp().expect("Expiry has been set but is missing on get"),
+            1720547781
+        );
 
         assert_eq!(url.exp().expect("Expiry has been set but is missing on get"), exp_time);
     }
 
+    #[test]
+    fn test_exp_opt() {
+        let mut url = EXAMPLE_URL.clone();
+        assert_eq!(url.exp_opt().unwrap(), None);
+
+        let exp = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_720_547_781);
+        url.set_exp(exp).unwrap();
+        assert_eq!(url.exp_opt().unwrap(), Some(exp));
+
+        let malformed = Url::parse("http://example.com?pj=https://test-payjoin-url#EX10").unwrap();
+        assert!(matches!(malformed.exp_opt(), Err(ParseExpParamError::InvalidExp(_))));
+    }
+
+    #[test]
+    fn test_exp_timestamp() {
+        let url = Url::parse("https://example.com/#EX1C4UC6ES").unwrap();
+        assert_eq!(url.exp_timestamp().unwrap(), 1720547781);
+        assert_eq!(
+            url.exp().unwrap(),
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1720547781)
+        );
+    }
+
+    #[test]
+    fn test_exp_unix_timestamp() {
+        let mut url = EXAMPLE_URL.clone();
+        assert!(!url.is_expired_at_timestamp(u32::MAX).unwrap(), "missing exp never expires");
+
+        url.set_exp_timestamp(1720547781);
+        assert_eq!(url.fragment(), Some("EX1C4UC6ES"));
+        assert_eq!(url.exp_timestamp().unwrap(), 1720547781);
+        assert!(!url.is_expired_at_timestamp(1720547781).unwrap());
+        assert!(url.is_expired_at_timestamp(1720547782).unwrap());
+
+        url.set_fragment(Some("EX1INVALIDBECH32"));
+        assert!(url.is_expired_at_timestamp(0).is_err());
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let mut url = EXAMPLE_URL.clone();
+        assert!(
+            !url.is_expired(std::time::SystemTime::now()).unwrap(),
+            "missing exp never expires"
+        );
+
+        let exp = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1720547781);
+        url.set_exp(exp).unwrap();
+        let one_sec = std::time::Duration::from_secs(1);
+        assert!(url.is_expired(exp + one_sec).unwrap());
+        assert!(!url.is_expired(exp).unwrap());
+        assert!(!url.is_expired(exp - one_sec).unwrap());
+
+        let invalid_exp_url = Url::parse("https://example.com/#EX10").unwrap();
+        assert!(matches!(invalid_exp_url.is_expired(exp), Err(ParseExpParamError::InvalidExp(_))));
+    }
+
+    #[test]
+    fn test_exp_out_of_range() {
+        let mut url = EXAMPLE_URL.clone();
+
+        let max = std::time::UNIX_EPOCH + std::time::Duration::from_secs(u32::MAX as u64);
+        url.set_exp(max).expect("u32::MAX should be encodable");
+        assert_eq!(url.exp().unwrap(), max);
+        assert_eq!(max, max_exp());
+
+        let past_max = max + std::time::Duration::from_secs(1);
+        assert!(matches!(url.set_exp(past_max), Err(SetExpError::Overflow(t)) if t == past_max));
+        assert_eq!(url.exp().unwrap(), max, "failed set should leave exp untouched");
+
+        let pre_epoch = std::time::UNIX_EPOCH - std::time::Duration::from_secs(1);
+        url.set_exp(pre_epoch).expect("pre-epoch times should clamp to 0");
+        assert_eq!(url.exp().unwrap(), std::time::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_exp_overflow_boundary() {
+        // 2106-02-07T06:28:15Z, the last second a u32 timestamp can hold
+        let last = std::time::UNIX_EPOCH + std::time::Duration::from_secs(4_294_967_295);
+        let mut url = EXAMPLE_URL.clone();
+        url.set_exp(last).expect("the last u32 second should be encodable");
+        assert_eq!(url.exp_timestamp().unwrap(), u32::MAX);
+
+        let overflow = last + std::time::Duration::from_secs(1);
+        let error = url.set_exp(overflow).unwrap_err();
+        assert!(matches!(error, SetExpError::Overflow(t) if t == overflow));
+        assert!(error.to_string().contains("too far in the future"));
+    }
+
+    #[test]
+    fn test_exp_clear() {
+        let mut url = EXAMPLE_URL.clone();
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        url.set_ohttp(OhttpKeys::from_str(ohttp).unwrap());
+        url.set_exp(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1720547781))
+            .unwrap();
+        assert_eq!(url.fragment(), Some(&format!("EX1C4UC6ES-{ohttp}")[..]));
+
+        url.clear_exp();
+        assert_eq!(url.fragment(), Some(ohttp));
+        assert!(matches!(url.exp(), Err(ParseExpParamError::MissingExp)));
+        assert!(url.ohttp().is_ok());
+
+        // clearing a missing parameter is a no-op
+        url.clear_exp();
+        assert_eq!(url.fragment(), Some(ohttp));
+
+        // removing the last parameter removes the fragment entirely
+        let mut url = EXAMPLE_URL.clone();
+        url.set_exp(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1720547781))
+            .unwrap();
+        url.clear_exp();
+        assert_eq!(url.fragment(), None);
+    }
+
     #[test]
     fn test_errors_when_parsing_exp() {
         let missing_exp_url = EXAMPLE_URL.clone();
         assert!(matches!(missing_exp_url.exp(), Err(ParseExpParamError::MissingExp)));
+        assert!(matches!(missing_exp_url.exp_timestamp(), Err(ParseExpParamError::MissingExp)));
 
         let invalid_fragment_exp_url =
             Url::parse("http://example.com?pj=https://test-payjoin-url#EX1invalid_bech_32")
@@ -364,18 +1469,28 @@ mod tests {
 
         let invalid_bech32_exp_url =
             Url::parse("http://example.com?pj=https://test-payjoin-url#EX1INVALIDBECH32").unwrap();
-        assert!(matches!(invalid_bech32_exp_url.exp(), Err(ParseExpParamError::DecodeBech32(_))));
+        assert!(matches!(
+            invalid_bech32_exp_url.exp(),
+            Err(ParseExpParamError::InvalidFragment(ParseFragmentError::InvalidBech32Char('I')))
+        ));
 
-        // Since the HRP is everything to the left of the right-most separator, the invalid url in
-        // this test would have it's HRP being parsed as EX101 instead of the expected EX1
+        // Since the HRP is everything to the left of the right-most separator, the param in this
+        // test has the HRP EX10 instead of EX, so it is not an exp param at all
         let invalid_hrp_exp_url =
             Url::parse("http://example.com?pj=https://test-payjoin-url#EX1010").unwrap();
-        assert!(matches!(invalid_hrp_exp_url.exp(), Err(ParseExpParamError::InvalidHrp(_))));
+        assert!(matches!(invalid_hrp_exp_url.exp(), Err(ParseExpParamError::MissingExp)));
 
         // Not enough data to decode into a u32
         let invalid_timestamp_exp_url =
             Url::parse("http://example.com?pj=https://test-payjoin-url#EX10").unwrap();
-        assert!(matches!(invalid_timestamp_exp_url.exp(), Err(ParseExpParamError::InvalidExp(_))));
+        assert!(matches!(invalid_timestamp_exp_url.exp(), Err(ParseExpParamError::InvalidExp(0))));
+
+        // Trailing data after the u32
+        let mut too_long_exp_url = EXAMPLE_URL.clone();
+        let too_long =
+            crate::bech32::nochecksum::encode(EX_HRP, &[0xc5, 0x79, 0x8d, 0x66, 0]).unwrap();
+        too_long_exp_url.set_fragment(Some(&too_long));
+        assert!(matches!(too_long_exp_url.exp(), Err(ParseExpParamError::InvalidExp(5))));
     }
 
     #[test]
@@ -398,21 +1513,52 @@ mod tests {
             Url::parse("http://example.com?pj=https://test-payjoin-url#RK1INVALIDBECH32").unwrap();
         assert!(matches!(
             invalid_bech32_receiver_pubkey_url.receiver_pubkey(),
-            Err(ParseReceiverPubkeyParamError::DecodeBech32(_))
+            Err(ParseReceiverPubkeyParamError::InvalidFragment(
+                ParseFragmentError::InvalidBech32Char('I')
+            ))
         ));
 
-        // Since the HRP is everything to the left of the right-most separator, the invalid url in
-        // this test would have it's HRP being parsed as RK101 instead of the expected RK1
+        // Since the HRP is everything to the left of the right-most separator, the param in this
+        // test has the HRP RK10 instead of RK, so it is not a receiver key param at all
         let invalid_hrp_receiver_pubkey_url =
             Url::parse("http://example.com?pj=https://test-payjoin-url#RK101").unwrap();
         assert!(matches!(
             invalid_hrp_receiver_pubkey_url.receiver_pubkey(),
-            Err(ParseReceiverPubkeyParamError::InvalidHrp(_))
+            Err(ParseReceiverPubkeyParamError::MissingPubkey)
         ));
 
-        // Not enough data to decode into a u32
-        let invalid_receiver_pubkey_url =
+        // An ohttp param where the receiver key was expected reports both HRPs
+        let ohttp_param = crate::bech32::nochecksum::encode(OH_HRP, &[2u8; 33]).unwrap();
+        let error = decode_receiver_pubkey(&ohttp_param).unwrap_err();
+        match &error {
+            ParseReceiverPubkeyParamError::InvalidHrp { expected, actual } => {
+                assert_eq!(**expected, RK_HRP);
+                assert_eq!(**actual, OH_HRP);
+            }
+            other => panic!("expected an hrp mismatch, got {other:?}"),
+        }
+        assert_eq!(error.to_string(), "incorrect hrp for receiver key: expected RK, got OH");
+
+        // Not enough data to decode into a compressed pubkey
+        let empty_receiver_pubkey_url =
             Url::parse("http://example.com?pj=https://test-payjoin-url#RK10").unwrap();
+        assert!(matches!(
+            empty_receiver_pubkey_url.receiver_pubkey(),
+            Err(ParseReceiverPubkeyParamError::InvalidPubkeyLength(0))
+        ));
+
+        let mut short_receiver_pubkey_url = EXAMPLE_URL.clone();
+        let short_pubkey = crate::bech32::nochecksum::encode(RK_HRP, &[2u8; 32]).unwrap();
+        short_receiver_pubkey_url.set_fragment(Some(&short_pubkey));
+        assert!(matches!(
+            short_receiver_pubkey_url.receiver_pubkey(),
+            Err(ParseReceiverPubkeyParamError::InvalidPubkeyLength(32))
+        ));
+
+        // Correct length but not a point on the curve
+        let mut invalid_receiver_pubkey_url = EXAMPLE_URL.clone();
+        let invalid_pubkey = crate::bech32::nochecksum::encode(RK_HRP, &[0u8; 33]).unwrap();
+        invalid_receiver_pubkey_url.set_fragment(Some(&invalid_pubkey));
         assert!(matches!(
             invalid_receiver_pubkey_url.receiver_pubkey(),
             Err(ParseReceiverPubkeyParamError::InvalidPubkey(_))
@@ -438,9 +1584,34 @@ mod tests {
         assert_eq!(format!("{pjuri}"), uri);
     }
 
+    #[test]
+    fn test_uppercase_v2_bip21() {
+        let uri =
+            "BITCOIN:TB1Q6D3A2W975YNY0ASUVD9A67NER4NKS58FF0Q8G4?PJOS=0&PJ=HTTPS://EXAMPLE.COM/\
+                   %23EX1XPK8Y6Q-OH1QYP87E2AVMDKXDTU6R25WCPQ5ZUF02XHNPA65JMD8ZA2W4YRQN6UUWG\
+                   -RK1QG2RH36X9ZWRK7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG";
+        let pjuri = Uri::try_from(uri).unwrap().assume_checked().check_pj_supported().unwrap();
+        assert!(pjuri.extras.is_v2());
+        assert!(pjuri.extras.endpoint().ohttp().is_ok());
+        assert_eq!(
+            pjuri.extras.output_substitution(),
+            crate::output_substitution::OutputSubstitution::Disabled
+        );
+        assert_eq!(pjuri.address.to_string(), "tb1q6d3a2w975yny0asuvd9a67ner4nks58ff0q8g4");
+
+        let serialized = pjuri.to_string();
+        assert_eq!(serialized.to_ascii_uppercase(), uri);
+        let reparsed = Uri::try_from(serialized.as_str())
+            .unwrap()
+            .assume_checked()
+            .check_pj_supported()
+            .unwrap();
+        assert_eq!(reparsed.extras.endpoint(), pjuri.extras.endpoint());
+    }
+
     #[test]
     fn test_failed_url_fragment() -> Result<(), BoxError> {
-        let expected_error = "LowercaseFragment";
+        let expected_error = "fragment is lowercase";
         let uri = "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?amount=0.01\
                    &pjos=0&pj=HTTPS://EXAMPLE.COM/\
                    %23oh1qypm5jxyns754y4r45qwe336qfx6zr8dqgvqculvztv20tfveydmfqc";
@@ -482,14 +1653,65 @@ mod tests {
             Some("EX1C4UC6ES+OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
         );
 
-        // Upon setting any value, the delimiter should be normalized to `-`
-        endpoint.set_exp(pjuri.extras.endpoint.exp().unwrap());
+        // Setting a value should preserve the delimiter too
+        endpoint.set_exp(pjuri.extras.endpoint.exp().unwrap()).unwrap();
+        assert_eq!(
+            endpoint.fragment(),
+            Some("EX1C4UC6ES+OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
+        );
+
+        // Only an explicit normalization rewrites the delimiter to `-`
+        endpoint.normalize_fragment().unwrap();
         assert_eq!(
             endpoint.fragment(),
             Some("EX1C4UC6ES-OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
         );
     }
 
+    #[test]
+    fn test_fragment_delimiter_single_param() {
+        // A fragment without any delimiter gets `-` once a second param is added
+        let mut endpoint = Url::parse("https://example.com/#EX1C4UC6ES").unwrap();
+        endpoint.set_ohttp(
+            OhttpKeys::from_str("OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
+                .unwrap(),
+        );
+        assert_eq!(
+            endpoint.fragment(),
+            Some("EX1C4UC6ES-OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
+        );
+
+        // Normalizing sorts the params and is a no-op on an already canonical fragment
+        let mut endpoint = Url::parse(
+            "https://example.com/#OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC+EX1C4UC6ES",
+        )
+        .unwrap();
+        endpoint.normalize_fragment().unwrap();
+        let normalized = endpoint.fragment().map(str::to_owned);
+        assert_eq!(
+            normalized.as_deref(),
+            Some("EX1C4UC6ES-OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
+        );
+        endpoint.normalize_fragment().unwrap();
+        assert_eq!(endpoint.fragment(), normalized.as_deref());
+    }
+
+    #[test]
+    fn test_canonicalize_fragment() {
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        let rk = "RK1QG2RH36X9ZWRK7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG";
+        let mut legacy =
+            Url::parse(&format!("https://example.com/pj#{rk}+{ohttp}+EX1C4UC6ES")).unwrap();
+        let mut canonical =
+            Url::parse(&format!("https://example.com/pj#EX1C4UC6ES-{ohttp}-{rk}")).unwrap();
+        assert_ne!(legacy, canonical);
+
+        legacy.canonicalize_fragment().unwrap();
+        canonical.canonicalize_fragment().unwrap();
+        assert_eq!(legacy, canonical);
+        assert_eq!(canonical.fragment(), Some(&format!("EX1C4UC6ES-{ohttp}-{rk}")[..]));
+    }
+
     #[test]
     fn test_fragment_lexicographical_order() {
         let uri = "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?amount=0.01\
@@ -507,21 +1729,189 @@ mod tests {
         );
 
         // Upon setting any value, the order should be normalized to lexicographical
-        endpoint.set_exp(pjuri.extras.endpoint.exp().unwrap());
+        endpoint.set_exp(pjuri.extras.endpoint.exp().unwrap()).unwrap();
         assert_eq!(
             endpoint.fragment(),
             Some("EX1C4UC6ES-OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
         );
     }
 
+    #[test]
+    fn test_mixed_delimiter_on_bip21() {
+        let uri = "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?amount=0.01\
+                   &pjos=0&pj=HTTPS://EXAMPLE.COM/\
+                   %23EX1C4UC6ES+OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC\
+                   -RK1Q0DJS3VVDXWQQTLQ8022QGXSX7ML9PHZ6EDSF6AKEWQG758JPS2EVGFA8W";
+        assert!(matches!(
+            Uri::try_from(uri),
+            Err(bitcoin_uri::de::Error::Extras(crate::uri::PjParseError(
+                crate::uri::error::InternalPjParseError::BadEndpoint(
+                    BadEndpointError::AmbiguousFragmentDelimiter
+                )
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_bech32_char_on_bip21() {
+        for c in ['B', 'I', 'O'] {
+            let uri = format!(
+                "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?amount=0.01\
+                 &pj=HTTPS://EXAMPLE.COM/%23EX1C4U{c}6ES"
+            );
+            assert!(matches!(
+                Uri::try_from(uri.as_str()),
+                Err(bitcoin_uri::de::Error::Extras(crate::uri::PjParseError(
+                    crate::uri::error::InternalPjParseError::BadEndpoint(
+                        BadEndpointError::Fragment(ParseFragmentError::InvalidBech32Char(e))
+                    )
+                ))) if e == c
+            ));
+        }
+    }
+
+    #[test]
+    fn test_fragment_length() {
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        let padding = "A".repeat(MAX_FRAGMENT_LENGTH - ohttp.len() - 1);
+        let at_limit = format!("https://example.com/#{ohttp}-{padding}");
+        assert!(parse_with_fragment(&at_limit).is_ok());
+
+        let too_long = format!("{at_limit}A");
+        assert!(matches!(
+            parse_with_fragment(&too_long),
+            Err(BadEndpointError::FragmentTooLong(len)) if len == MAX_FRAGMENT_LENGTH + 1
+        ));
+    }
+
+    #[test]
+    fn test_parse_with_fragment_port() {
+        let url = parse_with_fragment("https://example.com:8443/pj#EX1C4UC6ES").unwrap();
+        assert_eq!(url.domain(), Some("example.com"));
+        assert_eq!(url.port(), Some(8443));
+        assert!(url.exp().is_ok());
+    }
+
+    #[test]
+    fn test_duplicate_fragment_param() {
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        assert!(matches!(
+            parse_with_fragment(&format!("https://example.com/#{ohttp}-EX1C4UC6ES-{ohttp}")),
+            Err(BadEndpointError::DuplicateFragmentParam(hrp)) if hrp.as_str() == "OH"
+        ));
+        assert!(matches!(
+            parse_with_fragment(&format!("https://example.com/#{ohttp}+{ohttp}")),
+            Err(BadEndpointError::DuplicateFragmentParam(hrp)) if hrp.as_str() == "OH"
+        ));
+        assert!(parse_with_fragment(&format!("https://example.com/#{ohttp}")).is_ok());
+        assert!(parse_with_fragment(&format!("https://example.com/#{ohttp}-EX1C4UC6ES")).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_fragment_is_bad_endpoint() {
+        let rk = "RK1QG2RH36X9ZWRK7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG";
+        assert_eq!(
+            parse_with_fragment(&format!("https://example.com/#{rk}-{rk}-EX1C4UC6ES_")),
+            Err(BadEndpointError::Fragment(ParseFragmentError::InvalidChar('_')))
+        );
+    }
+
+    #[test]
+    fn test_empty_fragment_segment() {
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        for fragment in [
+            format!("-{ohttp}"),
+            format!("{ohttp}-"),
+            format!("{ohttp}--EX1C4UC6ES"),
+            format!("+{ohttp}"),
+            format!("{ohttp}++EX1C4UC6ES"),
+            "+".into(),
+        ] {
+            assert!(matches!(
+                parse_with_fragment(&format!("https://example.com/#{fragment}")),
+                Err(BadEndpointError::EmptyFragmentSegment)
+            ));
+        }
+        assert!(parse_with_fragment("https://example.com/#").is_ok());
+        assert!(parse_with_fragment(&format!("https://example.com/#{ohttp}-EX1C4UC6ES")).is_ok());
+    }
+
+    #[test]
+    fn test_parse_with_fragment_strict() {
+        let ex = "EX1C4UC6ES";
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        let rk = "RK1QG2RH36X9ZWRK7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG";
+
+        for fragment in
+            [format!("{ex}-{ohttp}-{rk}"), format!("{ex}+{ohttp}"), ohttp.into(), "".into()]
+        {
+            let endpoint = format!("https://example.com/#{fragment}");
+            assert!(parse_with_fragment_strict(&endpoint).is_ok(), "{endpoint}");
+        }
+        assert!(parse_with_fragment_strict("https://example.com").is_ok());
+
+        let reversed = format!("https://example.com/#{rk}-{ohttp}-{ex}");
+        assert!(parse_with_fragment(&reversed).is_ok());
+        assert_eq!(parse_with_fragment_strict(&reversed), Err(BadEndpointError::FragmentNotSorted));
+        assert_eq!(
+            parse_with_fragment_strict(&format!("https://example.com/#{ohttp}+{ex}")),
+            Err(BadEndpointError::FragmentNotSorted)
+        );
+        assert!(matches!(
+            parse_with_fragment_strict(&format!("https://example.com/#{ohttp}-{ex}-{ohttp}")),
+            Err(BadEndpointError::DuplicateFragmentParam(_))
+        ));
+
+        // OH sorts before OH0, even though the OH0 segment sorts before the OH one
+        let sorted = format!("https://example.com/#{ohttp}-OH01QQ");
+        assert!(parse_with_fragment_strict(&sorted).is_ok());
+        let mut canonical = Url::parse(&sorted).unwrap();
+        canonical.canonicalize_fragment().unwrap();
+        assert_eq!(canonical.as_str(), sorted);
+        assert_eq!(
+            parse_with_fragment_strict(&format!("https://example.com/#OH01QQ-{ohttp}")),
+            Err(BadEndpointError::FragmentNotSorted)
+        );
+    }
+
     #[test]
     fn test_fragment_mixed_delimiter() {
         // mixing current and deprecated delimiters should fail
         let fragment = "23RK1QG2RH36X9ZWRK\
 7UWCCQE0WD8T89XKK2W55KTK9UHSZLEG8Q2TGEGG-OH1QYP87E2AVMDKXDTU6R25WCPQ5ZUF02XHNPA65JMD8ZA2W4YRQN6UUWG+EX1XPK8Y6Q";
+        let error = check_fragment_delimiter(fragment).unwrap_err();
+        assert!(matches!(
+            error,
+            ParseFragmentError::AmbiguousDelimiter { plus_at: 117, dash_at: 58 }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "ambiguous fragment delimiter (+ found at 117 and - found at 58)"
+        );
+    }
+
+    #[test]
+    fn test_validate_fragment() {
+        let ohttp = "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC";
+        assert!(validate_fragment(&format!("EX1C4UC6ES-{ohttp}")).is_ok());
+        assert!(validate_fragment(&format!("EX1C4UC6ES+{ohttp}")).is_ok());
+        assert!(validate_fragment("").is_ok());
+
+        assert!(matches!(
+            validate_fragment(&format!("ex1c4uc6es-{ohttp}")),
+            Err(ParseFragmentError::InvalidChar('e'))
+        ));
+        assert!(matches!(
+            validate_fragment(&format!("EX1C4UC6ES-{ohttp}+RK1Q")),
+            Err(ParseFragmentError::AmbiguousDelimiter { .. })
+        ));
+        assert!(matches!(
+            validate_fragment(&format!("EX1C4UC6ES-{ohttp}#")),
+            Err(ParseFragmentError::InvalidChar('#'))
+        ));
         assert!(matches!(
-            check_fragment_delimiter(fragment),
-            Err(ParseFragmentError::AmbiguousDelimiter)
+            validate_fragment("EX1C4UB6ES"),
+            Err(ParseFragmentError::InvalidBech32Char('B'))
         ));
     }
 }
@@ -19,12 +19,34 @@ pub const ENCAPSULATED_MESSAGE_BYTES: usize = 8192;
 ///
 /// Note: This implementation assumes ephemeral public keys with sufficient entropy. The short length
 /// is an intentional tradeoff that provides adequate practical uniqueness while reducing DoS surface.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ShortId(pub [u8; 8]);
 
 impl ShortId {
     pub fn as_bytes(&self) -> &[u8] { &self.0 }
     pub fn as_slice(&self) -> &[u8] { &self.0 }
+
+    /// The path of this mailbox relative to the directory URL, e.g. for use with `Url::join`
+    pub fn subdirectory_path(&self) -> String { self.to_string() }
+
+    /// Compare two short IDs in constant time
+    ///
+    /// Use this rather than `==` when matching a requested short ID against known mailboxes in
+    /// memory, so the timing of the comparison does not reveal which mailboxes exist.
+    pub fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        use subtle::ConstantTimeEq;
+        self.0.ct_eq(&other.0)
+    }
+
+    /// Decode the short ID from the last non-empty path segment of a mailbox URL
+    #[cfg(feature = "_core")]
+    pub fn from_url_path(url: &url::Url) -> Result<ShortId, ShortIdError> {
+        let segment = url
+            .path_segments()
+            .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+            .ok_or(ShortIdError::MissingPathSegment)?;
+        segment.parse()
+    }
 }
 
 impl std::fmt::Display for ShortId {
@@ -42,7 +64,31 @@ impl std::fmt::Display for ShortId {
 #[derive(Debug)]
 pub enum ShortIdError {
     DecodeBech32(bitcoin::bech32::primitives::decode::CheckedHrpstringError),
-    IncorrectLength(std::array::TryFromSliceError),
+    /// The decoded identifier was not 8 bytes long, holds the actual length
+    IncorrectLength(usize),
+    /// The URL has no path segment to decode the identifier from
+    MissingPathSegment,
+}
+
+impl std::fmt::Display for ShortIdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShortIdError::DecodeBech32(e) => write!(f, "short ID is not valid bech32: {e}"),
+            ShortIdError::IncorrectLength(len) =>
+                write!(f, "short ID must be 8 bytes, got {len} bytes"),
+            ShortIdError::MissingPathSegment => write!(f, "URL path has no short ID segment"),
+        }
+    }
+}
+
+impl std::error::Error for ShortIdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShortIdError::DecodeBech32(e) => Some(e),
+            ShortIdError::IncorrectLength(_) => None,
+            ShortIdError::MissingPathSegment => None,
+        }
+    }
 }
 
 impl std::convert::From<bitcoin::hashes::sha256::Hash> for ShortId {
@@ -53,10 +99,20 @@ impl std::convert::From<bitcoin::hashes::sha256::Hash> for ShortId {
     }
 }
 
+/// Derive the mailbox [`ShortId`] of a receiver from its public key.
+#[cfg(feature = "v2")]
+impl std::convert::From<&crate::HpkePublicKey> for ShortId {
+    fn from(pubkey: &crate::HpkePublicKey) -> Self {
+        use bitcoin::hashes::Hash;
+        bitcoin::hashes::sha256::Hash::hash(&pubkey.to_compressed_bytes()).into()
+    }
+}
+
 impl std::convert::TryFrom<&[u8]> for ShortId {
     type Error = ShortIdError;
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        let bytes: [u8; 8] = bytes.try_into().map_err(ShortIdError::IncorrectLength)?;
+        let bytes: [u8; 8] =
+            bytes.try_into().map_err(|_| ShortIdError::IncorrectLength(bytes.len()))?;
         Ok(Self(bytes))
     }
 }
@@ -69,3 +125,112 @@ impl std::str::FromStr for ShortId {
         (&bytes[..]).try_into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_short_id_ct_eq() {
+        let id = ShortId([0, 1, 2, 3, 4, 5, 6, 7]);
+        assert!(bool::from(id.ct_eq(&ShortId([0, 1, 2, 3, 4, 5, 6, 7]))));
+        assert!(!bool::from(id.ct_eq(&ShortId([0, 1, 2, 3, 4, 5, 6, 8]))));
+        assert!(!bool::from(id.ct_eq(&ShortId([1, 1, 2, 3, 4, 5, 6, 7]))));
+    }
+
+    #[test]
+    fn test_short_id_roundtrip() {
+        let id = ShortId([0, 1, 2, 3, 4, 5, 6, 7]);
+        let encoded = id.to_string();
+        assert_eq!(ShortId::from_str(&encoded).unwrap(), id);
+        assert_eq!(ShortId::try_from(id.as_bytes()).unwrap(), id);
+    }
+
+    #[test]
+    fn test_short_id_incorrect_length() {
+        for len in [0, 7, 9, 33] {
+            let bytes = vec![0u8; len];
+            match ShortId::try_from(&bytes[..]) {
+                Err(ShortIdError::IncorrectLength(actual)) => assert_eq!(actual, len),
+                other => panic!("expected IncorrectLength({len}), got {other:?}"),
+            }
+        }
+
+        let too_long = crate::bech32::nochecksum::encode(
+            bitcoin::bech32::Hrp::parse("ID").unwrap(),
+            &[0u8; 9],
+        )
+        .unwrap();
+        assert!(matches!(
+            ShortId::from_str(too_long.strip_prefix("ID1").unwrap()),
+            Err(ShortIdError::IncorrectLength(9))
+        ));
+        assert!(matches!(ShortId::from_str("!"), Err(ShortIdError::DecodeBech32(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "_core")]
+    fn test_short_id_from_url_path() {
+        let id = ShortId([0, 1, 2, 3, 4, 5, 6, 7]);
+        let url = url::Url::parse(&format!("https://directory.example/{id}")).unwrap();
+        assert_eq!(ShortId::from_url_path(&url).unwrap(), id);
+        let url = url::Url::parse(&format!("https://directory.example/{id}/")).unwrap();
+        assert_eq!(ShortId::from_url_path(&url).unwrap(), id);
+
+        let url = url::Url::parse("https://directory.example/").unwrap();
+        assert!(matches!(ShortId::from_url_path(&url), Err(ShortIdError::MissingPathSegment)));
+        let url = url::Url::parse("mailto:payjoin@example.com").unwrap();
+        assert!(matches!(ShortId::from_url_path(&url), Err(ShortIdError::MissingPathSegment)));
+
+        let url = url::Url::parse("https://directory.example/QQ").unwrap();
+        assert!(matches!(ShortId::from_url_path(&url), Err(ShortIdError::IncorrectLength(1))));
+    }
+
+    #[test]
+    fn test_short_id_ordering() {
+        let a = ShortId([0, 0, 0, 0, 0, 0, 0, 1]);
+        let b = ShortId([0, 0, 0, 0, 0, 0, 1, 0]);
+        let c = ShortId([1, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut ids = vec![c, a, b, a];
+        ids.sort();
+        assert_eq!(ids, vec![a, a, b, c]);
+
+        let table: std::collections::BTreeMap<ShortId, &str> =
+            [(c, "c"), (a, "a"), (b, "b")].into_iter().collect();
+        assert_eq!(table.values().copied().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+
+        let set: std::collections::HashSet<ShortId> = [a, b, a].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "v2")]
+    fn test_short_id_from_pubkey() {
+        use bitcoin::hashes::{sha256, Hash};
+
+        let pubkey = crate::HpkeKeyPair::gen_keypair().public_key().clone();
+        let expected: ShortId = sha256::Hash::hash(&pubkey.to_compressed_bytes()).into();
+        assert_eq!(ShortId::from(&pubkey), expected);
+        assert_eq!(pubkey.short_id(), expected);
+    }
+
+    #[test]
+    #[cfg(feature = "v2")]
+    fn test_short_id_vector() {
+        use bitcoin::hex::FromHex;
+
+        // The compressed secp256k1 generator point
+        let bytes = <Vec<u8> as FromHex>::from_hex(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        let pubkey = crate::HpkePublicKey::from_compressed_bytes(&bytes).unwrap();
+        let id = pubkey.short_id();
+        assert_eq!(id, ShortId([15, 113, 91, 175, 93, 76, 46, 211]));
+        assert_eq!(id.subdirectory_path(), id.to_string());
+        assert!(!id.subdirectory_path().starts_with('/'));
+    }
+}